@@ -0,0 +1,91 @@
+struct LightColors {
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+};
+
+struct LightAttenuation {
+    float constant;
+    float linear;
+    float quadratic;
+};
+
+struct DirectionalLight {
+    vec3 direction;
+    LightColors colors;
+};
+
+struct PointLight {
+    vec3 position;
+    LightColors colors;
+    LightAttenuation attenuation;
+};
+
+struct Spotlight {
+    vec3 position;
+    vec3 direction;
+    float innerCutoff;
+    float outerCutoff;
+
+    LightColors colors;
+    LightAttenuation attenuation;
+};
+
+// What a light needs to know about the point it's lighting.
+struct Surface {
+    vec3 position;
+    vec3 normal;
+    vec3 diffuseColor;
+    vec3 specularColor;
+    float shininess;
+};
+
+
+float calculateAttenuation(LightAttenuation attenuation, float distanceToLight) {
+    return 1.0 / (
+        attenuation.constant +
+        attenuation.linear * distanceToLight +
+        attenuation.quadratic * distanceToLight * distanceToLight
+    );
+}
+
+
+// Phong lighting from a light in lightDirection. Only the diffuse and specular parts are scaled
+// by intensity, so spotlights still add ambient light outside their cone.
+vec3 calculatePhong(LightColors colors, vec3 lightDirection, Surface surface, vec3 viewDirection, float intensity) {
+    vec3 ambientLight = surface.diffuseColor * colors.ambient;
+
+    float diffuseStrength = max(dot(surface.normal, lightDirection), 0.0);
+    vec3 diffuseLight = diffuseStrength * surface.diffuseColor * colors.diffuse;
+
+    vec3 reflectionDirection = reflect(-lightDirection, surface.normal);
+    float specularStrength = pow(max(dot(viewDirection, reflectionDirection), 0.0), surface.shininess);
+    vec3 specularLight = specularStrength * surface.specularColor * colors.specular;
+
+    return ambientLight + (diffuseLight + specularLight) * intensity;
+}
+
+
+vec3 calculateDirectionalLight(DirectionalLight light, Surface surface, vec3 viewDirection) {
+    return calculatePhong(light.colors, normalize(-light.direction), surface, viewDirection, 1.0);
+}
+
+
+vec3 calculatePointLight(PointLight light, Surface surface, vec3 viewDirection) {
+    vec3 lightDirection = normalize(light.position - surface.position);
+    float attenuation = calculateAttenuation(light.attenuation, length(light.position - surface.position));
+
+    return calculatePhong(light.colors, lightDirection, surface, viewDirection, 1.0) * attenuation;
+}
+
+
+vec3 calculateSpotlight(Spotlight light, Surface surface, vec3 viewDirection) {
+    vec3 lightDirection = normalize(light.position - surface.position);
+    float theta = dot(lightDirection, normalize(-light.direction));
+    float epsilon = light.innerCutoff - light.outerCutoff;
+    float intensity = clamp((theta - light.outerCutoff) / epsilon, 0.0, 1.0);
+
+    float attenuation = calculateAttenuation(light.attenuation, length(light.position - surface.position));
+
+    return calculatePhong(light.colors, lightDirection, surface, viewDirection, intensity) * attenuation;
+}
@@ -2,10 +2,14 @@
 layout (location = 0) in vec3 aPos;
 layout (location = 1) in vec3 aNormal;
 layout (location = 2) in vec2 aTexCoord;
+layout (location = 3) in vec4 aColor;
+layout (location = 4) in vec4 aTangent;
 
 out vec3 FragmentPosition;
 out vec3 Normal;
 out vec2 TextureCoordinate;
+out vec4 VertexColor;
+out vec4 Tangent;
 
 uniform mat4 model;
 uniform mat4 view;
@@ -17,4 +21,6 @@ void main()
     FragmentPosition = vec3(model * vec4(aPos, 1.0));
     Normal = mat3(model) * aNormal;
     TextureCoordinate = aTexCoord;
+    VertexColor = aColor;
+    Tangent = vec4(mat3(model) * aTangent.xyz, aTangent.w);
 }
@@ -0,0 +1,9 @@
+#![no_main]
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+use learn_opengl::model_loader;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = model_loader::read_material_file(data, Path::new("/nonexistent/fuzz.mtl"));
+});
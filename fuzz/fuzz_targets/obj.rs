@@ -0,0 +1,10 @@
+#![no_main]
+use std::path::Path;
+
+use libfuzzer_sys::fuzz_target;
+use learn_opengl::model_loader;
+
+// Errors are fine, panics aren't. The path doesn't exist, so `mtllib` lines just fail to load.
+fuzz_target!(|data: &[u8]| {
+    let _ = model_loader::read_obj(data, Path::new("/nonexistent/fuzz.obj"));
+});
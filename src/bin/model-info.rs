@@ -0,0 +1,205 @@
+//! Prints what's in a model file and what might be wrong with it, for checking assets before
+//! they go in the game.
+//!
+//!     model-info [--json] <model file>
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+use serde_json::{json, Value};
+
+use learn_opengl::model_loader::{self, AttributeKind, Bounds, Model};
+
+
+fn main() {
+    let mut json_output = false;
+    let mut path = None;
+
+    for argument in env::args().skip(1) {
+        if argument == "--json" {
+            json_output = true;
+        } else if path.is_none() {
+            path = Some(argument);
+        } else {
+            usage();
+        }
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => usage()
+    };
+
+    // The loaders talk a lot, which would bury the report and break the JSON.
+    model_loader::set_quiet(true);
+
+    // Straight from the file, so the tool never leaves a cache behind.
+    let model = match model_loader::import_model(Path::new(&path)) {
+        Ok(model) => model,
+        Err(error) => {
+            eprintln!("Couldn't load {}: {}", path, error);
+            process::exit(1);
+        }
+    };
+
+    let report = Report::new(&model);
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&report.to_json(&path)).unwrap());
+    } else {
+        report.print(&path);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: model-info [--json] <model file>");
+    process::exit(2);
+}
+
+
+struct MaterialUsage {
+    name: String,
+    triangles: usize,
+    submeshes: usize
+}
+
+struct Report<'a> {
+    model: &'a Model,
+    vertices: usize,
+    triangles: usize,
+    degenerate_triangles: usize,
+    materials: Vec<MaterialUsage>
+}
+
+impl<'a> Report<'a> {
+    fn new(model: &'a Model) -> Report<'a> {
+        let mut materials: Vec<MaterialUsage> = model.materials.iter().map(|material| MaterialUsage {
+            name: material.name.clone(),
+            triangles: 0,
+            submeshes: 0
+        }).collect();
+
+        for submesh in &model.submeshes {
+            if let Some(usage) = materials.get_mut(submesh.material_index) {
+                usage.triangles += submesh.count / 3;
+                usage.submeshes += 1;
+            }
+        }
+
+        Report {
+            model,
+            vertices: model.vertices.len() / model.layout.stride.max(1),
+            triangles: model.indices.len() / 3,
+            degenerate_triangles: count_degenerate_triangles(model),
+            materials
+        }
+    }
+
+    fn print(&self, path: &str) {
+        let model = self.model;
+
+        println!("{}", path);
+        println!("Vertices: {}", self.vertices);
+        println!("Triangles: {}", self.triangles);
+        println!("Line segments: {}", model.line_indices.len() / 2);
+        println!("Points: {}", model.point_indices.len());
+        println!("Materials: {}", model.materials.len());
+
+        let object_names: Vec<&str> = model.objects.iter()
+            .map(|object| if object.name.is_empty() { "(unnamed)" } else { object.name.as_str() })
+            .collect();
+        println!("Objects: {} ({})", model.objects.len(), object_names.join(", "));
+
+        if model.bounds.is_empty() {
+            println!("Bounds: none, nothing is drawn");
+        } else {
+            println!("Bounds: {:?} to {:?}, size {:?}", model.bounds.min, model.bounds.max, model.bounds.size());
+            println!("Bounding sphere: center {:?}, radius {}", model.bounding_sphere.center, model.bounding_sphere.radius);
+        }
+
+        println!();
+        println!("Degenerate triangles: {}", self.degenerate_triangles);
+        println!("Face corners without normals: {}", model.missing_normals);
+        println!("Face corners without texture coordinates: {}", model.missing_texture_coordinates);
+
+        if !self.materials.is_empty() {
+            println!();
+            println!("Material usage:");
+            for usage in &self.materials {
+                println!("  {}: {} triangles in {} submeshes", usage.name, usage.triangles, usage.submeshes);
+            }
+        }
+    }
+
+    fn to_json(&self, path: &str) -> Value {
+        let model = self.model;
+
+        json!({
+            "path": path,
+            "vertices": self.vertices,
+            "triangles": self.triangles,
+            "line_segments": model.line_indices.len() / 2,
+            "points": model.point_indices.len(),
+            "objects": model.objects.iter().map(|object| object.name.as_str()).collect::<Vec<_>>(),
+            "bounds": bounds_json(&model.bounds),
+            "bounding_sphere": if model.bounds.is_empty() {
+                Value::Null
+            } else {
+                json!({ "center": model.bounding_sphere.center, "radius": model.bounding_sphere.radius })
+            },
+            "diagnostics": {
+                "degenerate_triangles": self.degenerate_triangles,
+                "corners_without_normals": model.missing_normals,
+                "corners_without_texture_coordinates": model.missing_texture_coordinates
+            },
+            "materials": self.materials.iter().map(|usage| json!({
+                "name": usage.name,
+                "triangles": usage.triangles,
+                "submeshes": usage.submeshes
+            })).collect::<Vec<_>>()
+        })
+    }
+}
+
+fn bounds_json(bounds: &Bounds) -> Value {
+    if bounds.is_empty() {
+        Value::Null
+    } else {
+        json!({ "min": bounds.min, "max": bounds.max, "size": bounds.size() })
+    }
+}
+
+/// Triangles that use the same vertex twice or have no area, so they can't be seen.
+fn count_degenerate_triangles(model: &Model) -> usize {
+    let position = match model.layout.get(AttributeKind::Position) {
+        Some(position) => position.offset,
+        None => return 0
+    };
+    let stride = model.layout.stride;
+    let read = |index: u32| {
+        let start = index as usize * stride + position;
+        [model.vertices[start], model.vertices[start + 1], model.vertices[start + 2]]
+    };
+
+    model.indices.chunks_exact(3).filter(|triangle| {
+        if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[0] == triangle[2] {
+            return true;
+        }
+
+        let (a, b, c) = (read(triangle[0]), read(triangle[1]), read(triangle[2]));
+        let edge1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let edge2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let normal = [
+            edge1[1] * edge2[2] - edge1[2] * edge2[1],
+            edge1[2] * edge2[0] - edge1[0] * edge2[2],
+            edge1[0] * edge2[1] - edge1[1] * edge2[0]
+        ];
+
+        // Relative to the edges, so tiny triangles in small models still count as fine.
+        length(normal) <= f32::EPSILON * length(edge1) * length(edge2)
+    }).count()
+}
+
+fn length(vector: [f32; 3]) -> f32 {
+    (vector[0] * vector[0] + vector[1] * vector[1] + vector[2] * vector[2]).sqrt()
+}
@@ -30,6 +30,13 @@ impl Camera {
         }
     }
 
+    /// Moves the camera straight above a sphere, far enough that all of it is in view. The field
+    /// of view is in radians, like the one given to `glm::ext::perspective`.
+    pub fn frame(&mut self, center: glm::Vector3<f32>, radius: f32, field_of_view: f32) {
+        let distance = radius / (field_of_view / 2.0).sin();
+        self.position = center + glm::vec3(0.0, distance, 0.0);
+    }
+
     pub fn get_view_matrix(&mut self) -> glm::Matrix4<f32> {
         glm::ext::look_at(self.position, self.position - glm::vec3(0.0, 1.0, 0.0), self.up)
     }
@@ -0,0 +1,3 @@
+//! The parts of the renderer that don't need an OpenGL context, so tools can use them too.
+
+pub mod model_loader;
@@ -6,9 +6,14 @@ extern crate image;
 use gl::types::*;
 use glfw::{ Context };
 use image::GenericImageView;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use learn_opengl::model_loader;
 
 mod camera;
-mod model_loader;
+mod preprocessor;
 mod program;
 
 
@@ -35,10 +40,37 @@ fn main() {
         gl::Viewport(0, 0, window_width as i32, window_height as i32);
         gl::ClearColor(0.1, 0.1, 0.1, 1.0);
         gl::Enable(gl::DEPTH_TEST);
+
+        // For materials that aren't fully opaque.
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+
+        // Single pixels are hard to see in point clouds.
+        gl::PointSize(3.0);
     }
 
-    // Create a shader program.
-    let target_shader_program = program::Program::new("target");
+    let point_lights: [glm::Vector3<f32>; 4] = [
+        glm::vec3( 0.7,  0.2,  2.0),
+        glm::vec3( 2.3, -3.3, -4.0),
+        glm::vec3(-4.0,  2.0, -12.0),
+        glm::vec3( 0.0,  0.0, -3.0)
+    ];
+
+    // Create a shader program, with room for all the point lights. Materials with textures need
+    // other variants of it, which are built when they're first drawn, and all of them get the
+    // unchanging uniforms set as soon as they're built.
+    let point_light_max = point_lights.len().to_string();
+    let mut target_shaders = match program::ShaderVariants::new(
+        "target",
+        &[("POINT_LIGHT_MAX", &point_light_max)],
+        move |program, features| set_lighting_uniforms(program, features, &point_lights)
+    ) {
+        Ok(variants) => variants,
+        Err(error) => {
+            eprintln!("Couldn't build the target shader: {}", error);
+            return;
+        }
+    };
 
     // Make a VAO!
     let mut target_vao = 0;
@@ -48,78 +80,85 @@ fn main() {
     }
 
     // Load the spaceship.
-    let spaceship_mesh = create_mesh("./assets/spaceship.obj");
+    let spaceship_mesh = match create_mesh("./assets/spaceship.obj") {
+        Ok(mesh) => mesh,
+        Err(error) => {
+            eprintln!("Couldn't load the spaceship: {}", error);
+            return;
+        }
+    };
+
+    // Scale every spaceship so its longest side is this long, whatever units the model is in.
+    let spaceship_size = 2.0;
+    let spaceship_scale = spaceship_mesh.model.bounds.unit_scale() * spaceship_size;
+    let spaceship_center = spaceship_mesh.model.bounds.center();
 
     // Make the vertex attribute pointers.
-    create_vertex_attribute_array::<f32>(0, 3, 8, 0);
-    create_vertex_attribute_array::<f32>(1, 3, 8, 3);
-    create_vertex_attribute_array::<f32>(2, 2, 8, 6);
+    let spaceship_layout = &spaceship_mesh.model.layout;
+    for attribute in spaceship_layout.attributes.iter() {
+        create_vertex_attribute_array::<f32>(
+            attribute_location(attribute.kind),
+            attribute.size as i32,
+            spaceship_layout.stride as i32,
+            attribute.offset
+        );
+    }
+
+    // Models without vertex colors read white from the color attribute, so they look the same.
+    if spaceship_layout.get(model_loader::AttributeKind::Color).is_none() {
+        unsafe {
+            gl::VertexAttrib4f(attribute_location(model_loader::AttributeKind::Color), 1.0, 1.0, 1.0, 1.0);
+        }
+    }
 
     // Get the texture.
     let _container_diffuse = create_texture("./assets/container.diffuse.png", gl::TEXTURE0, gl::RGBA);
     let _container_specular = create_texture("./assets/container.specular.png", gl::TEXTURE1, gl::RGBA);
 
-    // Set the unchanging uniforms.
-    let point_lights: [glm::Vector3<f32>; 4] = [
-        glm::vec3( 0.7,  0.2,  2.0),
-        glm::vec3( 2.3, -3.3, -4.0),
-        glm::vec3(-4.0,  2.0, -12.0),
-        glm::vec3( 0.0,  0.0, -3.0)
-    ];
-
-    target_shader_program.set_used();
-    // target_shader_program.set_signed_int("material.diffuseMap", 0);
-    // target_shader_program.set_signed_int("material.specularMap", 1);
-    // target_shader_program.set_float("material.shininess", 32.0);
-
-    target_shader_program.set_vector3("directionalLight.direction", glm::vec3(-0.2, -1.0, -0.3));
-    target_shader_program.set_vector3("light.colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("light.colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("light.colors.specular", glm::vec3(1.0, 1.0, 1.0));
-
-    target_shader_program.set_vector3("pointLights[0].position", point_lights[0]);
-    target_shader_program.set_vector3("pointLights[0].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("pointLights[0].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("pointLights[0].colors.specular", glm::vec3(1.0, 1.0, 1.0));
-    target_shader_program.set_float("pointLights[0].attenuation.constant", 1.0);
-    target_shader_program.set_float("pointLights[0].attenuation.linear", 0.09);
-    target_shader_program.set_float("pointLights[0].attenuation.quadratic", 0.032);
-
-    target_shader_program.set_vector3("pointLights[1].position", point_lights[1]);
-    target_shader_program.set_vector3("pointLights[1].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("pointLights[1].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("pointLights[1].colors.specular", glm::vec3(1.0, 1.0, 1.0));
-    target_shader_program.set_float("pointLights[1].attenuation.constant", 1.0);
-    target_shader_program.set_float("pointLights[1].attenuation.linear", 0.09);
-    target_shader_program.set_float("pointLights[1].attenuation.quadratic", 0.032);
-
-    target_shader_program.set_vector3("pointLights[2].position", point_lights[2]);
-    target_shader_program.set_vector3("pointLights[2].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("pointLights[2].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("pointLights[2].colors.specular", glm::vec3(1.0, 1.0, 1.0));
-    target_shader_program.set_float("pointLights[2].attenuation.constant", 1.0);
-    target_shader_program.set_float("pointLights[2].attenuation.linear", 0.09);
-    target_shader_program.set_float("pointLights[2].attenuation.quadratic", 0.032);
+    // Get the textures the spaceship's materials use. If one can't be loaded, that material just
+    // falls back to its flat color.
+    let mut material_textures: HashMap<PathBuf, u32> = HashMap::new();
+    for material in spaceship_mesh.model.materials.iter() {
+        for map in material.diffuse_map.iter().chain(material.bump_map.iter()) {
+            if material_textures.contains_key(&map.path) {
+                continue;
+            }
 
-    target_shader_program.set_vector3("pointLights[3].position", point_lights[3]);
-    target_shader_program.set_vector3("pointLights[3].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("pointLights[3].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("pointLights[3].colors.specular", glm::vec3(1.0, 1.0, 1.0));
-    target_shader_program.set_float("pointLights[3].attenuation.constant", 1.0);
-    target_shader_program.set_float("pointLights[3].attenuation.linear", 0.09);
-    target_shader_program.set_float("pointLights[3].attenuation.quadratic", 0.032);
+            match create_material_texture(map, gl::TEXTURE2) {
+                Ok(texture) => {
+                    material_textures.insert(map.path.clone(), texture);
+                }
+                Err(error) => {
+                    eprintln!("Couldn't load texture {:?}: {}", map.path, error);
+                }
+            }
+        }
+    }
 
-    target_shader_program.set_float("spotlight.innerCutoff", (glm::radians(12.5) as f32).cos());
-    target_shader_program.set_float("spotlight.outerCutoff", (glm::radians(17.0) as f32).cos());
-    target_shader_program.set_vector3("spotlight.colors.ambient", glm::vec3(0.2, 0.2, 0.2));
-    target_shader_program.set_vector3("spotlight.colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
-    target_shader_program.set_vector3("spotlight.colors.specular", glm::vec3(1.0, 1.0, 1.0));
-    target_shader_program.set_float("spotlight.attenuation.constant", 1.0);
-    target_shader_program.set_float("spotlight.attenuation.linear", 0.09);
-    target_shader_program.set_float("spotlight.attenuation.quadratic", 0.032);
+    // Which variant of the target shader each material is drawn with. Normal maps need tangents.
+    let has_tangents = spaceship_layout.get(model_loader::AttributeKind::Tangent).is_some();
+    let has_texture = |map: &Option<model_loader::TextureMap>| {
+        map.as_ref().is_some_and(|map| material_textures.contains_key(&map.path))
+    };
+    let material_features: Vec<program::Features> = spaceship_mesh.model.materials.iter().map(|material| {
+        let mut features = program::Features::NONE;
+        if has_texture(&material.diffuse_map) {
+            features |= program::Features::DIFFUSE_MAP;
+        }
+        if has_tangents && has_texture(&material.bump_map) {
+            features |= program::Features::NORMAL_MAP;
+        }
+        features
+    }).collect();
 
     // Make a new shader for our lamp.
-    let lamp_shader_program = program::Program::new("lamp");
+    let mut lamp_shader_program = match program::Program::new("lamp") {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("Couldn't build the lamp shader: {}", error);
+            return;
+        }
+    };
 
     // Make a new VAO for the lamp.
     let mut lamp_vao = 0;
@@ -129,11 +168,24 @@ fn main() {
     }
 
     // Load the cube mesh for the lamp.
-    let cube_mesh = create_mesh("./assets/cube.obj");
+    let cube_mesh = match create_mesh("./assets/cube.obj") {
+        Ok(mesh) => mesh,
+        Err(error) => {
+            eprintln!("Couldn't load the lamp cube: {}", error);
+            return;
+        }
+    };
 
     // Make a new attribute array for it. We leave out the normals and texture coordinates,
     // because they're not important.
-    create_vertex_attribute_array::<f32>(0, 3, 8, 0);
+    let cube_layout = &cube_mesh.model.layout;
+    let cube_position_attribute = cube_layout.get(model_loader::AttributeKind::Position).unwrap();
+    create_vertex_attribute_array::<f32>(
+        attribute_location(cube_position_attribute.kind),
+        cube_position_attribute.size as i32,
+        cube_layout.stride as i32,
+        cube_position_attribute.offset
+    );
 
     // Let's draw a debug line. Make a VAO for it.
     let mut lines_vao = 0;
@@ -159,7 +211,8 @@ fn main() {
     create_vertex_attribute_array::<f32>(0, 3, 3, 0);
 
     // Set up the projection matrix (this doesn't change).
-    let projection_matrix = glm::ext::perspective(glm::radians(45.0), window_width as f32 / window_height as f32, 0.1, 100.0);
+    let field_of_view = glm::radians(45.0);
+    let projection_matrix = glm::ext::perspective(field_of_view, window_width as f32 / window_height as f32, 0.1, 100.0);
 
     // Set up the basic camera.
     let mut camera = camera::Camera::new(5.0, glm::vec3(0.0, 0.0, -1.0), glm::vec3(0.0, 10.0, 0.0));
@@ -192,34 +245,59 @@ fn main() {
 
         let view_matrix = camera.get_view_matrix();
 
+        // Pick up edits to the shaders. The target shader's variants get their unchanging
+        // uniforms set again as they're reloaded.
+        target_shaders.reload_if_changed();
+        lamp_shader_program.reload_if_changed();
+
         // Do rendering stuff.
         unsafe {
             gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
             // Render the target cube.
-            target_shader_program.set_used();
-
-            target_shader_program.set_vector3("viewerPosition", camera.position);
-            target_shader_program.set_vector3("spotlight.position", camera.position);
-            target_shader_program.set_vector3("spotlight.direction", -camera.z_axis);
-
-            target_shader_program.set_matrix("view", view_matrix);
-            target_shader_program.set_matrix("projection", projection_matrix);
-
             gl::BindVertexArray(target_vao);
 
             for i in 0..10 {
                 let mut model_matrix = glm::ext::translate(&identity_matrix(), cube_positions[i]);
                 let angle = 20.0 * i as f32;
                 model_matrix = glm::ext::rotate(&model_matrix, glm::radians(angle), glm::vec3(1.0, 0.3, 0.5));
-                model_matrix = glm::ext::scale(&model_matrix, glm::vec3(0.2, 0.2, 0.2));
-                target_shader_program.set_matrix("model", model_matrix);
-
-                let mut vertices_rendered = 0;
-
-                // Iterate over the materials.
-                for index in 0..spaceship_mesh.model.materials.len() {
-                    let material = &spaceship_mesh.model.materials[index];
+                model_matrix = glm::ext::scale(&model_matrix, glm::vec3(spaceship_scale, spaceship_scale, spaceship_scale));
+                model_matrix = glm::ext::translate(
+                    &model_matrix,
+                    -glm::vec3(spaceship_center[0], spaceship_center[1], spaceship_center[2])
+                );
+
+                // How many pixels tall the spaceship's bounding sphere is on screen.
+                let distance = glm::length(camera.position - cube_positions[i]).max(0.001);
+                let radius = spaceship_mesh.model.bounding_sphere.radius * spaceship_scale;
+                let screen_size = radius / (distance * (field_of_view / 2.0).tan()) * window_height as f32;
+                let lod = spaceship_mesh.select_lod(screen_size);
+
+                let mut indices_rendered = 0;
+
+                // The variant the last submesh was drawn with. Switching to another one means
+                // setting everything that changes between frames and spaceships on it.
+                let mut current_features = None;
+
+                // Iterate over the submeshes.
+                for submesh in lod.submeshes.iter() {
+                    let material = &spaceship_mesh.model.materials[submesh.material_index];
+                    let features = material_features[submesh.material_index];
+                    let target_shader_program = target_shaders.get(features);
+
+                    if current_features != Some(features) {
+                        target_shader_program.set_used();
+
+                        target_shader_program.set_vector3("viewerPosition", camera.position);
+                        target_shader_program.set_vector3("spotlight.position", camera.position);
+                        target_shader_program.set_vector3("spotlight.direction", -camera.z_axis);
+
+                        target_shader_program.set_matrix("view", view_matrix);
+                        target_shader_program.set_matrix("projection", projection_matrix);
+                        target_shader_program.set_matrix("model", model_matrix);
+
+                        current_features = Some(features);
+                    }
 
                     target_shader_program.set_vector3(
                         "material.diffuseColor",
@@ -248,15 +326,40 @@ fn main() {
                     );
 
                     target_shader_program.set_float("material.shininess", material.shininess);
+                    target_shader_program.set_float("material.dissolve", material.dissolve);
+
+                    if features.contains(program::Features::DIFFUSE_MAP) {
+                        if let Some(map) = &material.diffuse_map {
+                            gl::ActiveTexture(gl::TEXTURE2);
+                            gl::BindTexture(gl::TEXTURE_2D, material_textures[&map.path]);
+                        }
+                    }
+                    if features.contains(program::Features::NORMAL_MAP) {
+                        if let Some(map) = &material.bump_map {
+                            gl::ActiveTexture(gl::TEXTURE3);
+                            gl::BindTexture(gl::TEXTURE_2D, material_textures[&map.path]);
+                            target_shader_program.set_float("material.normalMapStrength", map.bump_multiplier);
+                        }
+                    }
 
-                    let vertices_to_render = spaceship_mesh.model.material_indices[index] - vertices_rendered;
+                    spaceship_mesh.draw_range(submesh.start, submesh.count);
+                    indices_rendered += submesh.count as i32;
+                }
 
-                    gl::DrawArrays(gl::TRIANGLES, vertices_rendered, vertices_to_render);
-                    vertices_rendered = spaceship_mesh.model.material_indices[index];
+                if indices_rendered < lod.count as i32 {
+                    panic!("Only rendered {} out of {}!", indices_rendered, lod.count);
                 }
 
-                if vertices_rendered < spaceship_mesh.size {
-                    panic!("Only rendered {} out of {}!", vertices_rendered, spaceship_mesh.size);
+                // Lines and points don't have anything to light, so they're drawn flat with the
+                // lamp shader, like the debug line.
+                if !spaceship_mesh.lines.is_empty() || !spaceship_mesh.points.is_empty() {
+                    lamp_shader_program.set_used();
+                    lamp_shader_program.set_matrix("model", model_matrix);
+                    lamp_shader_program.set_matrix("view", view_matrix);
+                    lamp_shader_program.set_matrix("projection", projection_matrix);
+
+                    spaceship_mesh.draw_elements(gl::LINES, spaceship_mesh.lines.start, spaceship_mesh.lines.len());
+                    spaceship_mesh.draw_elements(gl::POINTS, spaceship_mesh.points.start, spaceship_mesh.points.len());
                 }
             }
 
@@ -272,7 +375,7 @@ fn main() {
                 lamp_shader_program.set_matrix("view", view_matrix);
                 lamp_shader_program.set_matrix("projection", projection_matrix);
 
-                gl::DrawArrays(gl::TRIANGLES, 0, cube_mesh.size);
+                cube_mesh.draw_range(0, cube_mesh.size as usize);
             }
 
             // Render the debug line.
@@ -297,6 +400,13 @@ fn main() {
                     window.set_should_close(true);
                 }
 
+                // Home brings the first spaceship back into view. Its bounding sphere is centered
+                // on its bounding box, which is moved onto its position when it's drawn.
+                glfw::WindowEvent::Key(glfw::Key::Home, _, glfw::Action::Press, _) => {
+                    let radius = spaceship_mesh.model.bounding_sphere.radius * spaceship_scale;
+                    camera.frame(cube_positions[0], radius, field_of_view);
+                }
+
                 // glfw::WindowEvent::CursorPos(cursor_x, cursor_y) => {
                 //     if first_mouse_input {
                 //         previous_cursor_x = cursor_x;
@@ -343,12 +453,138 @@ fn main() {
 
 struct Mesh {
     id: u32,
+    element_buffer: u32,
+    index_type: GLenum,
     size: i32,
-    model: model_loader::Model
+    model: model_loader::Model,
+    /// The model's own triangles, then simpler and simpler versions of them. They're all in the
+    /// same element buffer, one after the other.
+    lods: Vec<MeshLod>,
+    /// Where the model's line segments and points are in the element buffer, after the LODs.
+    lines: Range<usize>,
+    points: Range<usize>
+}
+
+struct MeshLod {
+    /// These point into the whole element buffer, not just this LOD's part of it.
+    submeshes: Vec<model_loader::Submesh>,
+    count: usize,
+    error: f32
 }
 
-fn create_mesh(path: &'static str) -> Mesh {
-    let model = model_loader::load_model(path);
+/// How many LODs to make after the full-detail one, and how much simpler each one is.
+const LOD_LEVELS: usize = 3;
+const LOD_REDUCTION: f32 = 0.5;
+/// LODs aren't allowed to move the surface further than this, relative to the model's size.
+const LOD_MAX_ERROR: f32 = 0.05;
+/// How many pixels a LOD's error can cover before a more detailed one is used instead.
+const LOD_PIXEL_ERROR: f32 = 1.0;
+
+impl Mesh {
+    /// Picks the simplest LOD that still looks right when the model's bounding sphere is
+    /// `screen_size` pixels across.
+    fn select_lod(&self, screen_size: f32) -> &MeshLod {
+        let model_size = self.model.bounding_sphere.radius * 2.0;
+
+        self.lods.iter().rev()
+            .find(|lod| lod.error / model_size * screen_size < LOD_PIXEL_ERROR)
+            .unwrap_or(&self.lods[0])
+    }
+
+    /// Draws `count` indices starting at `start` as triangles. The mesh's VAO needs to be bound.
+    fn draw_range(&self, start: usize, count: usize) {
+        self.draw_elements(gl::TRIANGLES, start, count);
+    }
+
+    /// Like `draw_range`, but for any kind of primitive.
+    fn draw_elements(&self, mode: GLenum, start: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        let index_size = if self.index_type == gl::UNSIGNED_SHORT {
+            std::mem::size_of::<u16>()
+        } else {
+            std::mem::size_of::<u32>()
+        };
+
+        unsafe {
+            gl::DrawElements(mode, count as i32, self.index_type, (start * index_size) as *const _);
+        }
+    }
+}
+
+/// Sets the uniforms that don't change from frame to frame.
+fn set_lighting_uniforms(target_shader_program: &program::Program, features: program::Features, point_lights: &[glm::Vector3<f32>; 4]) {
+    target_shader_program.set_used();
+    // target_shader_program.set_signed_int("material.diffuseMap", 0);
+    // target_shader_program.set_signed_int("material.specularMap", 1);
+    // target_shader_program.set_float("material.shininess", 32.0);
+    if features.contains(program::Features::DIFFUSE_MAP) {
+        target_shader_program.set_signed_int("material.diffuseMap", 2);
+    }
+    if features.contains(program::Features::NORMAL_MAP) {
+        target_shader_program.set_signed_int("material.normalMap", 3);
+    }
+
+    target_shader_program.set_vector3("directionalLight.direction", glm::vec3(-0.2, -1.0, -0.3));
+    target_shader_program.set_vector3("directionalLight.colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("directionalLight.colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("directionalLight.colors.specular", glm::vec3(1.0, 1.0, 1.0));
+
+    target_shader_program.set_vector3("pointLights[0].position", point_lights[0]);
+    target_shader_program.set_vector3("pointLights[0].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("pointLights[0].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("pointLights[0].colors.specular", glm::vec3(1.0, 1.0, 1.0));
+    target_shader_program.set_float("pointLights[0].attenuation.constant", 1.0);
+    target_shader_program.set_float("pointLights[0].attenuation.linear", 0.09);
+    target_shader_program.set_float("pointLights[0].attenuation.quadratic", 0.032);
+
+    target_shader_program.set_vector3("pointLights[1].position", point_lights[1]);
+    target_shader_program.set_vector3("pointLights[1].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("pointLights[1].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("pointLights[1].colors.specular", glm::vec3(1.0, 1.0, 1.0));
+    target_shader_program.set_float("pointLights[1].attenuation.constant", 1.0);
+    target_shader_program.set_float("pointLights[1].attenuation.linear", 0.09);
+    target_shader_program.set_float("pointLights[1].attenuation.quadratic", 0.032);
+
+    target_shader_program.set_vector3("pointLights[2].position", point_lights[2]);
+    target_shader_program.set_vector3("pointLights[2].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("pointLights[2].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("pointLights[2].colors.specular", glm::vec3(1.0, 1.0, 1.0));
+    target_shader_program.set_float("pointLights[2].attenuation.constant", 1.0);
+    target_shader_program.set_float("pointLights[2].attenuation.linear", 0.09);
+    target_shader_program.set_float("pointLights[2].attenuation.quadratic", 0.032);
+
+    target_shader_program.set_vector3("pointLights[3].position", point_lights[3]);
+    target_shader_program.set_vector3("pointLights[3].colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("pointLights[3].colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("pointLights[3].colors.specular", glm::vec3(1.0, 1.0, 1.0));
+    target_shader_program.set_float("pointLights[3].attenuation.constant", 1.0);
+    target_shader_program.set_float("pointLights[3].attenuation.linear", 0.09);
+    target_shader_program.set_float("pointLights[3].attenuation.quadratic", 0.032);
+
+    target_shader_program.set_float("spotlight.innerCutoff", (glm::radians(12.5) as f32).cos());
+    target_shader_program.set_float("spotlight.outerCutoff", (glm::radians(17.0) as f32).cos());
+    target_shader_program.set_vector3("spotlight.colors.ambient", glm::vec3(0.2, 0.2, 0.2));
+    target_shader_program.set_vector3("spotlight.colors.diffuse", glm::vec3(0.5, 0.5, 0.5));
+    target_shader_program.set_vector3("spotlight.colors.specular", glm::vec3(1.0, 1.0, 1.0));
+    target_shader_program.set_float("spotlight.attenuation.constant", 1.0);
+    target_shader_program.set_float("spotlight.attenuation.linear", 0.09);
+    target_shader_program.set_float("spotlight.attenuation.quadratic", 0.032);
+}
+
+fn create_mesh(path: &'static str) -> Result<Mesh, model_loader::ModelError> {
+    // Big scans take a while, so say how it's going every tenth of the way.
+    let mut reported = 0;
+    let mut model = model_loader::load_model_with_progress(path, &mut |fraction| {
+        let tenths = (fraction * 10.0) as i32;
+        if tenths > reported {
+            reported = tenths;
+            println!("Loading {}: {}%", path, tenths * 10);
+        }
+    })?;
+    model.generate_tangents();
     let mesh: &[f32] = &model.vertices;
 
     let mut vbo = 0;
@@ -363,11 +599,65 @@ fn create_mesh(path: &'static str) -> Mesh {
         );
     }
 
-    Mesh {
+    // The LODs reuse the model's vertices, so their indices just go after the model's.
+    let mut all_indices = model.indices.clone();
+    let mut lods = vec![MeshLod {
+        submeshes: model.submeshes.clone(),
+        count: model.indices.len(),
+        error: 0.0
+    }];
+
+    for lod in model.generate_lods(LOD_LEVELS, LOD_REDUCTION, LOD_MAX_ERROR) {
+        let base = all_indices.len();
+        lods.push(MeshLod {
+            submeshes: lod.submeshes.iter()
+                .map(|submesh| model_loader::Submesh { start: submesh.start + base, ..*submesh })
+                .collect(),
+            count: lod.indices.len(),
+            error: lod.error
+        });
+        all_indices.extend_from_slice(&lod.indices);
+    }
+
+    let lines = all_indices.len()..all_indices.len() + model.line_indices.len();
+    all_indices.extend_from_slice(&model.line_indices);
+    let points = all_indices.len()..all_indices.len() + model.point_indices.len();
+    all_indices.extend_from_slice(&model.point_indices);
+
+    // Small meshes can get away with half-size indices.
+    let (ebo, index_type) = if mesh.len() / model.layout.stride <= u16::MAX as usize + 1 {
+        let indices: Vec<u16> = all_indices.iter().map(|&index| index as u16).collect();
+        (create_element_buffer(&indices), gl::UNSIGNED_SHORT)
+    } else {
+        (create_element_buffer(&all_indices), gl::UNSIGNED_INT)
+    };
+
+    Ok(Mesh {
         id: vbo,
-        size: (mesh.len() / 8) as i32,
-        model: model
+        element_buffer: ebo,
+        index_type,
+        size: model.indices.len() as i32,
+        model,
+        lods,
+        lines,
+        points
+    })
+}
+
+fn create_element_buffer<T>(indices: &[T]) -> u32 {
+    let mut ebo = 0;
+    unsafe {
+        gl::GenBuffers(1, &mut ebo);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            std::mem::size_of_val(indices) as isize,
+            indices.as_ptr() as *const _,
+            gl::STATIC_DRAW
+        );
     }
+
+    ebo
 }
 
 
@@ -381,6 +671,17 @@ impl HasOpenGLType for f32 {
     }
 }
 
+/// The `layout (location = ...)` each kind of vertex attribute is bound to in the shaders.
+fn attribute_location(kind: model_loader::AttributeKind) -> u32 {
+    match kind {
+        model_loader::AttributeKind::Position => 0,
+        model_loader::AttributeKind::Normal => 1,
+        model_loader::AttributeKind::TextureCoordinate => 2,
+        model_loader::AttributeKind::Color => 3,
+        model_loader::AttributeKind::Tangent => 4
+    }
+}
+
 fn create_vertex_attribute_array<T: HasOpenGLType>(index: u32, size: i32, stride: i32, offset: usize) {
     unsafe {
         gl::VertexAttribPointer(
@@ -401,6 +702,23 @@ fn create_texture(path: &'static str, texture_spot: u32, pixel_type: u32) -> u32
     let (image_width, image_height) = image_obj.dimensions();
     let image_data = image_obj.raw_pixels();
 
+    upload_texture(image_width, image_height, &image_data, texture_spot, pixel_type)
+}
+
+/// Like `create_texture`, but for maps that come out of model files, so a bad path or image is
+/// an error instead of a crash. The image is always converted to RGBA.
+fn create_material_texture(map: &model_loader::TextureMap, texture_spot: u32) -> image::ImageResult<u32> {
+    let image_obj = match &map.data {
+        Some(data) => image::load_from_memory(data)?,
+        None => image::open(&map.path)?
+    };
+    let (image_width, image_height) = image_obj.dimensions();
+    let image_data = image_obj.to_rgba().into_raw();
+
+    Ok(upload_texture(image_width, image_height, &image_data, texture_spot, gl::RGBA))
+}
+
+fn upload_texture(image_width: u32, image_height: u32, image_data: &[u8], texture_spot: u32, pixel_type: u32) -> u32 {
     // Load the texture.
     let mut texture = 0;
     unsafe {
@@ -1,259 +1,1199 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::io::{self, BufRead};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use memmap2::Mmap;
+
+use self::obj_parser::{Command, ElementKind};
+
+/// Like `println!`, unless the loaders have been told to be quiet.
+macro_rules! log {
+    ($($argument:tt)*) => {
+        if !$crate::model_loader::is_quiet() {
+            println!($($argument)*);
+        }
+    };
+}
+
+mod cache;
+mod gltf;
+mod obj_parser;
+mod obj_writer;
+mod ply;
+mod resolver;
+mod simplify;
+mod stl;
+mod tangents;
+
+pub use self::cache::{cache_path, load_cache, save_cache};
+pub use self::obj_writer::save_obj;
+pub use self::resolver::{FileResolver, MemoryResolver, Resolver};
+pub use self::simplify::Lod;
+
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Stops the loaders from saying what they're up to on stdout, for tools whose output is meant
+/// for other programs. Errors are still returned as usual.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Where in a model or material file something went wrong. Lines and columns start at 1.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io { path: PathBuf, error: io::Error },
+    InvalidNumber { location: Location, token: String },
+    NotEnoughNumbers { location: Location, expected: usize, found: usize },
+    MissingArgument { location: Location, keyword: String },
+    MalformedFace { location: Location, token: String },
+    IndexOutOfBounds { location: Location, kind: &'static str, index: isize, count: usize },
+    UnknownMaterial { location: Location, name: String },
+    NotEnoughFacePoints { location: Location, found: usize },
+    NotEnoughLinePoints { location: Location, found: usize },
+    InvalidOption { location: Location, option: String, value: String },
+    UnsupportedFormat { path: PathBuf },
+    /// For binary and JSON formats, where there aren't lines and columns to point at.
+    Malformed { path: PathBuf, reason: String }
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io { path, error } => {
+                write!(f, "{}: {}", path.display(), error)
+            }
+            ModelError::InvalidNumber { location, token } => {
+                write!(f, "{}: couldn't parse \"{}\" as a number", location, token)
+            }
+            ModelError::NotEnoughNumbers { location, expected, found } => {
+                write!(f, "{}: expected at least {} numbers, found {}", location, expected, found)
+            }
+            ModelError::MissingArgument { location, keyword } => {
+                write!(f, "{}: \"{}\" is missing its argument", location, keyword)
+            }
+            ModelError::MalformedFace { location, token } => {
+                write!(f, "{}: malformed face point \"{}\"", location, token)
+            }
+            ModelError::IndexOutOfBounds { location, kind, index, count } => {
+                write!(f, "{}: {} index {} is out of bounds (there are {})", location, kind, index, count)
+            }
+            ModelError::UnknownMaterial { location, name } => {
+                write!(f, "{}: couldn't find material with name {}", location, name)
+            }
+            ModelError::NotEnoughFacePoints { location, found } => {
+                write!(f, "{}: a face needs at least 3 points, found {}", location, found)
+            }
+            ModelError::NotEnoughLinePoints { location, found } => {
+                write!(f, "{}: a line needs at least 2 points, found {}", location, found)
+            }
+            ModelError::InvalidOption { location, option, value } => {
+                write!(f, "{}: \"{}\" isn't a valid value for {}", location, value, option)
+            }
+            ModelError::UnsupportedFormat { path } => {
+                write!(f, "{}: don't know how to load this kind of file", path.display())
+            }
+            ModelError::Malformed { path, reason } => {
+                write!(f, "{}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl Error for ModelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ModelError::Io { error, .. } => Some(error),
+            _ => None
+        }
+    }
+}
+
+
+/// A whitespace-separated word on a line, along with the column it starts at.
+struct Token<'a> {
+    column: usize,
+    text: &'a str
+}
+
+/// Splits a line into tokens, remembering where it came from so errors can point back at it.
+#[derive(Clone)]
+struct Tokens<'a> {
+    path: &'a Path,
+    line_number: usize,
+    line: &'a str,
+    offset: usize
+}
+
+impl<'a> Tokens<'a> {
+    fn new(path: &'a Path, line_number: usize, line: &'a str) -> Tokens<'a> {
+        Tokens { path, line_number, line, offset: 0 }
+    }
+
+    fn location(&self, column: usize) -> Location {
+        Location {
+            path: self.path.to_path_buf(),
+            line: self.line_number,
+            column
+        }
+    }
+
+    /// The column just past the end of the line, for errors about something that's missing.
+    fn end_location(&self) -> Location {
+        self.location(self.line.len() + 1)
+    }
+
+    /// Everything from the start of `token` to the end of the line, for arguments that can
+    /// contain spaces (like file names).
+    fn remainder(&self, token: &Token<'a>) -> &'a str {
+        self.line[token.column - 1..].trim_end()
+    }
+
+    /// Looks at the next token without consuming it.
+    fn peek(&self) -> Option<Token<'a>> {
+        self.clone().next()
+    }
+
+    fn next_argument(&mut self, keyword: &str) -> Result<Token<'a>, ModelError> {
+        match self.next() {
+            Some(token) => Ok(token),
+            None => Err(ModelError::MissingArgument {
+                location: self.end_location(),
+                keyword: String::from(keyword)
+            })
+        }
+    }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let rest = &self.line[self.offset..];
+        let start = self.offset + rest.find(|c: char| !c.is_whitespace())?;
+        let rest = &self.line[start..];
+        let length = rest.find(char::is_whitespace).unwrap_or(rest.len());
+
+        self.offset = start + length;
+
+        Some(Token {
+            column: start + 1,
+            text: &self.line[start..start + length]
+        })
+    }
+}
 
 enum LineType {
     Vertex,
     Normal,
     Texture,
     Face,
+    Line,
+    Point,
     Comment,
     MaterialReference,
     UseMaterial,
+    SmoothingGroup,
+    Object,
+    Group,
     Unknown
 }
 
+#[derive(Debug, Clone, Copy)]
+struct FacePoint {
+    vertex_index: usize,
+    texture_index: usize,
+    normal_index: usize
+}
+
+/// One triangle of a face, after bigger faces have been split up.
 #[derive(Debug)]
-struct Vertex {
-    x: f32,
-    y: f32,
-    z: f32,
-    w: f32
+struct Face {
+    points: [FacePoint; 3],
+    smoothing_group: u32,
+    /// Which face in the file this triangle came from, so flat-shaded polygons can share their
+    /// corners between their triangles.
+    polygon: usize
 }
 
-impl Vertex {
-    fn from_numbers(numbers: Vec<f32>) -> Vertex {
-        if numbers.len() < 3 {
-            panic!("Not enough numbers to make a vertex!")
-        }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttributeKind {
+    Position,
+    Normal,
+    TextureCoordinate,
+    Color,
+    /// Four floats: the tangent, then the sign of the bitangent. See `Model::generate_tangents`.
+    Tangent
+}
 
-        Vertex {
-            x: numbers[0],
-            y: numbers[1],
-            z: numbers[2],
-            w: if numbers.len() > 3 { numbers[3] } else { 1.0 }
+/// One attribute inside each vertex of `Model::vertices`. Sizes and offsets are counted in floats.
+#[derive(Debug, Clone, Copy)]
+pub struct VertexAttribute {
+    pub kind: AttributeKind,
+    pub size: usize,
+    pub offset: usize
+}
+
+/// How the floats in `Model::vertices` are interleaved. Stride is counted in floats.
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+    pub stride: usize
+}
+
+impl VertexLayout {
+    /// Position, then normal, then texture coordinate. This is what the OBJ loader always emits.
+    pub fn standard() -> VertexLayout {
+        VertexLayout {
+            attributes: vec![
+                VertexAttribute { kind: AttributeKind::Position, size: 3, offset: 0 },
+                VertexAttribute { kind: AttributeKind::Normal, size: 3, offset: 3 },
+                VertexAttribute { kind: AttributeKind::TextureCoordinate, size: 2, offset: 6 }
+            ],
+            stride: 8
         }
     }
+
+    /// Adds another attribute after the existing ones.
+    pub fn with(mut self, kind: AttributeKind, size: usize) -> VertexLayout {
+        self.attributes.push(VertexAttribute { kind, size, offset: self.stride });
+        self.stride += size;
+        self
+    }
+
+    pub fn get(&self, kind: AttributeKind) -> Option<&VertexAttribute> {
+        self.attributes.iter().find(|attribute| attribute.kind == kind)
+    }
 }
 
-#[derive(Debug)]
-struct Normal {
-    x: f32,
-    y: f32,
-    z: f32
+/// A run of `Model::indices` drawn with one material.
+#[derive(Debug, Clone, Copy)]
+pub struct Submesh {
+    pub material_index: usize,
+    pub start: usize,
+    pub count: usize,
+    pub bounds: Bounds,
+    pub bounding_sphere: BoundingSphere
 }
 
-impl Normal {
-    fn from_numbers(numbers: Vec<f32>) -> Normal {
-        if numbers.len() < 3 {
-            panic!("Not enough numbers to make a normal!")
+/// An axis-aligned bounding box. An empty one has `min` above `max`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3]
+}
+
+impl Bounds {
+    pub fn empty() -> Bounds {
+        Bounds {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3]
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|axis| self.min[axis] > self.max[axis])
+    }
+
+    /// Grows the box to cover `point`.
+    pub fn add(&mut self, point: [f32; 3]) {
+        for ((min, max), value) in self.min.iter_mut().zip(self.max.iter_mut()).zip(point.iter()) {
+            *min = min.min(*value);
+            *max = max.max(*value);
+        }
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        if self.is_empty() {
+            return [0.0; 3];
         }
+        [
+            (self.min[0] + self.max[0]) / 2.0,
+            (self.min[1] + self.max[1]) / 2.0,
+            (self.min[2] + self.max[2]) / 2.0
+        ]
+    }
 
-        Normal {
-            x: numbers[0],
-            y: numbers[1],
-            z: numbers[2]
+    pub fn size(&self) -> [f32; 3] {
+        if self.is_empty() {
+            return [0.0; 3];
         }
+        [self.max[0] - self.min[0], self.max[1] - self.min[1], self.max[2] - self.min[2]]
+    }
+
+    /// The uniform scale that makes the longest side of the box 1 long. Scale around `center` to
+    /// fit the box inside a unit cube at the origin.
+    pub fn unit_scale(&self) -> f32 {
+        let size = self.size();
+        let longest = size[0].max(size[1]).max(size[2]);
+
+        if longest > 0.0 { 1.0 / longest } else { 1.0 }
     }
 }
 
-#[derive(Debug)]
-struct Texture {
-    x: f32,
-    y: f32
+/// A sphere around the center of a bounding box that holds every vertex inside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: [f32; 3],
+    pub radius: f32
 }
 
-impl Texture {
-    fn from_numbers(numbers: Vec<f32>) -> Texture {
-        if numbers.len() < 2 {
-            panic!("Not enough numbers to make a texture coordinate!")
-        }
+impl BoundingSphere {
+    pub fn empty() -> BoundingSphere {
+        BoundingSphere { center: [0.0; 3], radius: 0.0 }
+    }
+}
+
+/// Where a vertex's normal came from. Two face points only share a vertex if their normals match.
+#[derive(PartialEq, Eq, Hash)]
+enum NormalSource {
+    File(usize),
+    Smooth(u32),
+    /// The polygon the corner belongs to.
+    Flat(usize),
+    /// Lines and points without normals in the file get a zero one.
+    Missing
+}
+
+pub struct Model {
+    pub vertices: Vec<f32>,
+    pub indices: Vec<u32>,
+    pub layout: VertexLayout,
+    pub materials: Vec<Material>,
+    /// In file order. Every triangle is in exactly one submesh, so drawing them all draws the whole
+    /// model. Several submeshes can share a material, and none of them cross an object or group
+    /// boundary.
+    pub submeshes: Vec<Submesh>,
+    pub objects: Vec<Object>,
+    /// Pairs of indices into `vertices`, one pair per line segment. OBJ `l` elements end up here,
+    /// apart from `indices`, so everything that expects triangles can ignore them.
+    pub line_indices: Vec<u32>,
+    /// Indices into `vertices` to draw as points, from OBJ `p` elements.
+    pub point_indices: Vec<u32>,
+    /// How many face corners the file didn't give a normal or texture coordinate for. Missing
+    /// normals are worked out from the faces, and missing texture coordinates are zero.
+    pub missing_normals: usize,
+    pub missing_texture_coordinates: usize,
+    /// Every file the model was read from, so a cache of it knows when it's out of date.
+    pub sources: Vec<PathBuf>,
+    pub bounds: Bounds,
+    pub bounding_sphere: BoundingSphere
+}
+
+impl Model {
+    pub fn object(&self, name: &str) -> Option<&Object> {
+        self.objects.iter().find(|object| object.name == name)
+    }
+
+    /// Works out the bounds of the whole model and of each submesh from the vertex positions.
+    /// Loading does this already, but anything that moves the vertices afterwards should call it.
+    pub fn compute_bounds(&mut self) {
+        let position = match self.layout.get(AttributeKind::Position) {
+            Some(position) => position.offset,
+            None => return
+        };
+        let stride = self.layout.stride;
+        let vertices = &self.vertices;
+        let indices = &self.indices;
+        let read = |index: u32| {
+            let start = index as usize * stride + position;
+            [vertices[start], vertices[start + 1], vertices[start + 2]]
+        };
+
+        // Only count vertices that are actually drawn.
+        let bounds_of = |index_lists: &[&[u32]]| {
+            let mut bounds = Bounds::empty();
+            for &index in index_lists.iter().flat_map(|list| list.iter()) {
+                bounds.add(read(index));
+            }
+
+            let center = bounds.center();
+            let mut radius_squared: f32 = 0.0;
+            for &index in index_lists.iter().flat_map(|list| list.iter()) {
+                let point = read(index);
+                let offset = [point[0] - center[0], point[1] - center[1], point[2] - center[2]];
+                radius_squared = radius_squared.max(offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2]);
+            }
+
+            (bounds, BoundingSphere { center, radius: radius_squared.sqrt() })
+        };
 
-        Texture {
-            x: numbers[0],
-            y: numbers[1]
+        for submesh in self.submeshes.iter_mut() {
+            let (bounds, sphere) = bounds_of(&[&indices[submesh.start..submesh.start + submesh.count]]);
+            submesh.bounds = bounds;
+            submesh.bounding_sphere = sphere;
         }
+
+        let (bounds, sphere) = bounds_of(&[indices, &self.line_indices, &self.point_indices]);
+        self.bounds = bounds;
+        self.bounding_sphere = sphere;
     }
 }
 
+/// A named part of the model, from an `o` line. Faces that come before any `o` line end up in an
+/// object with an empty name.
 #[derive(Debug)]
-struct FacePoint {
-    vertex_index: usize,
-    texture_index: usize,
-    normal_index: usize
+pub struct Object {
+    pub name: String,
+    /// The range of `Model::indices` covered by this object.
+    pub start: usize,
+    pub count: usize,
+    /// The range of `Model::submeshes` needed to draw this object with its materials.
+    pub submeshes: Range<usize>,
+    pub groups: Vec<Group>
+}
+
+impl Object {
+    /// Finds the first group that has `name` among its names.
+    pub fn group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.names.iter().any(|group_name| group_name == name))
+    }
 }
 
+/// A run of faces inside an object, from a `g` line. A `g` line can give several names at once.
 #[derive(Debug)]
-struct Face {
-    points: Vec<FacePoint>
+pub struct Group {
+    pub names: Vec<String>,
+    pub start: usize,
+    pub count: usize,
+    pub submeshes: Range<usize>
 }
 
-pub struct Model {
-    pub vertices: Vec<f32>,
-    pub materials: Vec<Material>,
-    pub material_indices: Vec<i32>
+
+/// Loads a model, picking the loader based on the file's extension. After the first load the
+/// model is saved to a cache next to the file, which is used instead until the file changes.
+pub fn load_model<P: AsRef<Path>>(path: P) -> Result<Model, ModelError> {
+    load_model_with_progress(path, &mut |_| ())
+}
+
+/// Like `load_model`, but calls `progress` every so often with how far along it is, from 0 to 1.
+pub fn load_model_with_progress<P: AsRef<Path>>(path: P, progress: &mut dyn FnMut(f32)) -> Result<Model, ModelError> {
+    let path = path.as_ref();
+    let cache = cache_path(path);
+
+    match load_cache(&cache) {
+        Ok(Some(model)) => {
+            log!("Loaded {:?} from the cache {:?}.", path, cache);
+            progress(1.0);
+            return Ok(model);
+        }
+        Ok(None) => (),
+        Err(error) => log!("Ignoring the cache: {}", error)
+    }
+
+    let model = import_model_with_progress(path, progress)?;
+    if let Err(error) = save_cache(&model, &cache) {
+        log!("Couldn't save the cache: {}", error);
+    }
+
+    Ok(model)
 }
 
+/// Loads a model straight from the file, without looking at the cache.
+pub fn import_model(path: &Path) -> Result<Model, ModelError> {
+    import_model_with_progress(path, &mut |_| ())
+}
+
+/// Like `import_model`, but calls `progress` every so often with how far along it is, from 0 to 1.
+/// Only OBJ files report anything before they're done.
+pub fn import_model_with_progress(path: &Path, progress: &mut dyn FnMut(f32)) -> Result<Model, ModelError> {
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    let mut model = match extension.as_deref() {
+        Some("obj") => load_obj(path, progress)?,
+        Some("gltf") | Some("glb") => gltf::load_gltf(path)?,
+        Some("ply") => ply::load_ply(path)?,
+        Some("stl") => stl::load_stl(path)?,
+        _ => return Err(ModelError::UnsupportedFormat { path: path.to_path_buf() })
+    };
+
+    model.compute_bounds();
+    log!("Bounds are {:?} to {:?}.", model.bounds.min, model.bounds.max);
+    progress(1.0);
+
+    Ok(model)
+}
+
+fn load_obj(path: &Path, progress: &mut dyn FnMut(f32)) -> Result<Model, ModelError> {
+    log!("Reading file {:?}...", path);
+    let file = File::open(path).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    // Safe as long as nobody truncates the file while we're reading it.
+    let bytes = unsafe { Mmap::map(&file) }.map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    parse_obj(&bytes, path, &FileResolver, progress)
+}
+
+/// Reads an OBJ file from anywhere, like memory. `path` is only used for error messages and to
+/// find the files it refers to, so it doesn't have to exist itself.
+pub fn read_obj<R: BufRead>(reader: R, path: &Path) -> Result<Model, ModelError> {
+    read_obj_with(reader, path, &FileResolver)
+}
+
+/// Like `read_obj`, but the MTL files and textures it refers to are found by `resolver`.
+pub fn read_obj_with<R: BufRead>(mut reader: R, path: &Path, resolver: &dyn Resolver) -> Result<Model, ModelError> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    let mut model = parse_obj(&bytes, path, resolver, &mut |_| ())?;
+    model.compute_bounds();
 
-pub fn load_model(filename: &'static str) -> Model {
-    let mut filepath = PathBuf::from(filename);
-    println!("Reading file {:?}...", filepath);
-    let file = File::open(&filepath).expect("Couldn't open the file!");
+    Ok(model)
+}
 
-    let buffer = BufReader::new(file);
-    let mut vertices: Vec<Vertex> = vec![];
-    let mut textures: Vec<Texture> = vec![];
-    let mut normals: Vec<Normal> = vec![];
+fn parse_obj(bytes: &[u8], path: &Path, resolver: &dyn Resolver, progress: &mut dyn FnMut(f32)) -> Result<Model, ModelError> {
+    let path = path.to_path_buf();
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut textures: Vec<[f32; 2]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
     let mut faces: Vec<Face> = vec![];
+    let mut polygon_count = 0;
+    let mut line_segments: Vec<[FacePoint; 2]> = vec![];
+    let mut loose_points: Vec<FacePoint> = vec![];
     let mut file_materials: HashMap<String, Material> = HashMap::new();
-    let mut material_indices: Vec<i32> = vec![];
+    let mut submeshes: Vec<Submesh> = vec![];
+    let mut current_material: Option<usize> = None;
+    let mut objects: Vec<Object> = vec![Object {
+        name: String::new(),
+        start: 0,
+        count: 0,
+        submeshes: 0..0,
+        groups: vec![]
+    }];
     let mut model_materials: Vec<Material> = vec![];
+    let mut material_lookup: HashMap<String, usize> = HashMap::new();
+    let mut smoothing_group = 0;
+    let mut sources = vec![path.clone()];
+    let mut missing_normals = 0;
+    let mut missing_texture_coordinates = 0;
 
-    'lines: for line in buffer.lines() {
-        let good_line = line.expect("Couldn't read the line!");
-        let mut tokens = good_line.split_whitespace();
+    // Chunks are parsed a few at a time, so there's work for every thread while only a batch's
+    // worth of parsed chunks is held at once. Their commands are replayed in order straight away,
+    // since indices and materials depend on what came before. The resolved faces still pile up
+    // for the whole file, because a vertex's normal can depend on any face that touches it.
+    let spans = obj_parser::split(bytes);
+    let batch_size = rayon::current_num_threads() * 2;
+    let mut bytes_read = 0;
+    // Reused for every face, so faces don't allocate.
+    let mut face_points: Vec<FacePoint> = vec![];
+    let mut corners: Vec<[f32; 3]> = vec![];
 
-        let first_token = tokens.next();
+    for batch in spans.chunks(batch_size) {
+        for (span, chunk) in batch.iter().zip(obj_parser::parse_all(&path, batch)) {
+            let chunk = chunk?;
 
-        let line_type = match first_token {
-            Some(token) => {
-                if token == "v" {
-                    LineType::Vertex
-                } else if token == "vt" {
-                    LineType::Texture
-                } else if token == "vn" {
-                    LineType::Normal
-                } else if token == "f" {
-                    LineType::Face
-                } else if token == "#" {
-                    LineType::Comment
-                } else if token == "mtllib" {
-                    LineType::MaterialReference
-                } else if token == "usemtl" {
-                    LineType::UseMaterial
-                } else {
-                    LineType::Unknown
-                }
-            }
-            None => { continue 'lines }
-        };
+            // How many of each element came before this chunk, to resolve its indices against.
+            let bases = [positions.len(), textures.len(), normals.len()];
+            positions.extend_from_slice(&chunk.positions);
+            textures.extend_from_slice(&chunk.texture_coordinates);
+            normals.extend_from_slice(&chunk.normals);
 
-        match line_type {
-            LineType::Vertex => {
-                vertices.push(Vertex::from_numbers(parse_numbers(tokens)));
-            }
-            LineType::Normal => {
-                normals.push(Normal::from_numbers(parse_numbers(tokens)));
-            }
-            LineType::Texture => {
-                textures.push(Texture::from_numbers(parse_numbers(tokens)));
-            }
-            LineType::MaterialReference => {
-                filepath.set_file_name(tokens.next().unwrap());
-                file_materials = load_material_file(&filepath);
-            }
+            for command in &chunk.commands {
+                match *command {
+                    Command::Element { kind, line, ref points, counts } => {
+                        face_points.clear();
+                        for point in &chunk.points[points.clone()] {
+                            let mut resolved = [0; 3];
+                            for (slot, kind) in ["vertex", "texture", "normal"].iter().enumerate() {
+                                if let Some(index) = point.indices[slot] {
+                                    resolved[slot] = resolve_index(index, kind, bases[slot] + counts[slot], || Location {
+                                        path: path.clone(),
+                                        line,
+                                        column: point.column
+                                    })?;
+                                }
+                            }
+
+                            face_points.push(FacePoint {
+                                vertex_index: resolved[0],
+                                texture_index: resolved[1],
+                                normal_index: resolved[2]
+                            });
+                        }
+
+                        match kind {
+                            ElementKind::Line => {
+                                // Polylines are split into segments, like faces into triangles.
+                                for pair in face_points.windows(2) {
+                                    line_segments.push([pair[0], pair[1]]);
+                                }
+                                continue;
+                            }
+                            ElementKind::Point => {
+                                loose_points.extend_from_slice(&face_points);
+                                continue;
+                            }
+                            ElementKind::Face => ()
+                        }
+
+                        // Faces before any usemtl still get drawn, with a plain material.
+                        if current_material.is_none() {
+                            model_materials.push(plain_material());
+                            current_material = Some(model_materials.len() - 1);
+                            submeshes.push(Submesh {
+                                material_index: model_materials.len() - 1,
+                                start: faces.len() * 3,
+                                count: 0,
+                                bounds: Bounds::empty(),
+                                bounding_sphere: BoundingSphere::empty()
+                            });
+                        }
 
-            LineType::Face => {
-                let mut face_points: Vec<FacePoint> = vec![];
+                        missing_normals += face_points.iter().filter(|point| point.normal_index == 0).count();
+                        missing_texture_coordinates += face_points.iter().filter(|point| point.texture_index == 0).count();
 
-                for token in tokens {
-                    let indices: Vec<usize> = token.split("/").map(|index| {
-                        if index == "" {
-                            0
-                        } else {
-                            index.parse::<usize>().unwrap()
+                        let polygon = polygon_count;
+                        polygon_count += 1;
+
+                        // Everything downstream assumes triangles, so split bigger faces up here.
+                        if face_points.len() == 3 {
+                            faces.push(Face { points: [face_points[0], face_points[1], face_points[2]], smoothing_group, polygon });
+                            continue;
                         }
-                    }).collect();
 
-                    if indices.len() < 1 {
-                        panic!("Malformed face tag!");
+                        corners.clear();
+                        corners.extend(face_points.iter().map(|point| positions[point.vertex_index - 1]));
+                        for triangle in triangulate(&corners) {
+                            faces.push(Face {
+                                points: [face_points[triangle[0]], face_points[triangle[1]], face_points[triangle[2]]],
+                                smoothing_group,
+                                polygon
+                            });
+                        }
                     }
 
-                    face_points.push(FacePoint {
-                        vertex_index: indices[0],
-                        texture_index: if indices.len() > 1 { indices[1] } else { 0 },
-                        normal_index: if indices.len() > 2 { indices[2] } else { 0 }
-                    });
-                }
+                    Command::MaterialLibrary { name } => {
+                        let material_path = resolver.resolve(name, &path);
+                        log!("Reading file {:?}...", material_path);
+                        let reader = resolver.open(&material_path)
+                            .map_err(|error| ModelError::Io { path: material_path.clone(), error })?;
 
-                faces.push(Face { points: face_points });
-            }
+                        file_materials = read_material_file_with(reader, &material_path, resolver)?;
+                        sources.push(material_path);
+                    }
 
-            LineType::UseMaterial => {
-                let material_name = tokens.next().unwrap();
+                    Command::UseMaterial { line, column, name } => {
+                        log!("Using material {}", name);
 
-                println!("Using material {}", material_name);
+                        // Materials are only copied into the model the first time they're used.
+                        let material_index = match material_lookup.get(name) {
+                            Some(&index) => index,
+                            None => match file_materials.remove(name) {
+                                Some(material) => {
+                                    model_materials.push(material);
+                                    material_lookup.insert(String::from(name), model_materials.len() - 1);
+                                    model_materials.len() - 1
+                                }
+                                None => {
+                                    return Err(ModelError::UnknownMaterial {
+                                        location: Location { path: path.clone(), line, column },
+                                        name: String::from(name)
+                                    });
+                                }
+                            }
+                        };
 
-                match file_materials.remove(material_name) {
-                    Some(material) => {
-                        model_materials.push(material);
+                        close_submesh(&mut submeshes, faces.len() * 3);
+                        submeshes.push(Submesh {
+                            material_index,
+                            start: faces.len() * 3,
+                            count: 0,
+                            bounds: Bounds::empty(),
+                            bounding_sphere: BoundingSphere::empty()
+                        });
+                        current_material = Some(material_index);
                     }
-                    None => {
-                        panic!("Couldn't find material with name {}!", material_name);
+
+                    Command::Object { name } => {
+                        log!("Found object {}", name);
+
+                        close_submesh(&mut submeshes, faces.len() * 3);
+                        close_object(&mut objects, faces.len() * 3, submeshes.len());
+                        objects.push(Object {
+                            name: String::from(name),
+                            start: faces.len() * 3,
+                            count: 0,
+                            submeshes: submeshes.len()..submeshes.len(),
+                            groups: vec![]
+                        });
+
+                        // Keep drawing with the same material in the new object.
+                        if let Some(material_index) = current_material {
+                            submeshes.push(Submesh {
+                                material_index,
+                                start: faces.len() * 3,
+                                count: 0,
+                                bounds: Bounds::empty(),
+                                bounding_sphere: BoundingSphere::empty()
+                            });
+                        }
                     }
-                }
 
-                if faces.len() > 0 {
-                    material_indices.push((faces.len() * 3) as i32);
+                    Command::Group { ref names } => {
+                        let mut names: Vec<String> = chunk.names[names.clone()].iter().map(|&name| String::from(name)).collect();
+                        if names.is_empty() {
+                            names.push(String::from("default"));
+                        }
+                        log!("Found group {:?}", names);
+
+                        close_submesh(&mut submeshes, faces.len() * 3);
+                        let object = objects.last_mut().unwrap();
+                        close_group(object, faces.len() * 3, submeshes.len());
+                        object.groups.push(Group {
+                            names,
+                            start: faces.len() * 3,
+                            count: 0,
+                            submeshes: submeshes.len()..submeshes.len()
+                        });
+
+                        if let Some(material_index) = current_material {
+                            submeshes.push(Submesh {
+                                material_index,
+                                start: faces.len() * 3,
+                                count: 0,
+                                bounds: Bounds::empty(),
+                                bounding_sphere: BoundingSphere::empty()
+                            });
+                        }
+                    }
+
+                    Command::SmoothingGroup(group) => {
+                        smoothing_group = group;
+                    }
                 }
             }
 
-            LineType::Unknown => {
-                println!("Unknown line type: {}", good_line);
-            }
-            LineType::Comment => ()
+            // Reading the file is most of the work, so it gets most of the progress bar.
+            bytes_read += span.bytes.len();
+            progress(0.9 * bytes_read as f32 / bytes.len() as f32);
         }
     }
 
-    material_indices.push((faces.len() * 3) as i32);
+    close_submesh(&mut submeshes, faces.len() * 3);
+    close_object(&mut objects, faces.len() * 3, submeshes.len());
+
+    log!("Computing missing normals...");
+    let face_normals: Vec<[f32; 3]> = faces.iter().map(|face| {
+        let a = positions[face.points[0].vertex_index - 1];
+        let b = positions[face.points[1].vertex_index - 1];
+        let c = positions[face.points[2].vertex_index - 1];
+
+        cross([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]])
+    }).collect();
+
+    // Flat normals are per polygon rather than per triangle, so a polygon's corners are shared
+    // between its triangles.
+    let mut polygon_normals = vec![[0.0; 3]; polygon_count];
+    for (face, face_normal) in faces.iter().zip(&face_normals) {
+        let sum = &mut polygon_normals[face.polygon];
+        sum[0] += face_normal[0];
+        sum[1] += face_normal[1];
+        sum[2] += face_normal[2];
+    }
+
+    // Faces in the same smoothing group share a normal at each position. The face normals aren't
+    // normalized yet, so bigger faces pull harder.
+    let mut smooth_normals: HashMap<(usize, u32), [f32; 3]> = HashMap::new();
+    for (face, face_normal) in faces.iter().zip(&face_normals) {
+        if face.smoothing_group == 0 {
+            continue;
+        }
+
+        for point in &face.points {
+            let sum = smooth_normals.entry((point.vertex_index, face.smoothing_group)).or_insert([0.0; 3]);
+            sum[0] += face_normal[0];
+            sum[1] += face_normal[1];
+            sum[2] += face_normal[2];
+        }
+    }
 
-    println!("Parsing file...");
+    log!("Building vertices...");
+    let layout = VertexLayout::standard();
     let mut out: Vec<f32> = vec![];
+    let mut indices: Vec<u32> = vec![];
+    let mut unique_vertices: HashMap<(usize, usize, NormalSource), u32> = HashMap::new();
 
-    for face in faces {
-        for point in face.points {
-            if vertices.len() < point.vertex_index {
-                panic!("Out of bounds vertex index specified!");
-            }
+    // Every index was bounds-checked when it was read, so these can't go out of range. The
+    // normal is only worked out for vertices that haven't been seen before.
+    let mut add_vertex = |point: &FacePoint, normal_source: NormalSource, normal: &dyn Fn() -> [f32; 3]| {
+        let key = (point.vertex_index, point.texture_index, normal_source);
+        if let Some(&index) = unique_vertices.get(&key) {
+            return index;
+        }
 
-            out.push(vertices[point.vertex_index - 1].x);
-            out.push(vertices[point.vertex_index - 1].y);
-            out.push(vertices[point.vertex_index - 1].z);
+        let index = (out.len() / layout.stride) as u32;
+        unique_vertices.insert(key, index);
 
-            if point.normal_index > 0 {
-                if normals.len() < point.normal_index {
-                    panic!("Out of bounds normal index specified!");
-                }
+        out.extend_from_slice(&positions[point.vertex_index - 1]);
 
-                out.push(normals[point.normal_index - 1].x);
-                out.push(normals[point.normal_index - 1].y);
-                out.push(normals[point.normal_index - 1].z);
-            }
+        if point.normal_index > 0 {
+            out.extend_from_slice(&normals[point.normal_index - 1]);
+        } else {
+            out.extend_from_slice(&normal());
+        }
 
-            if point.texture_index > 0 {
-                if textures.len() < point.texture_index {
-                    panic!("Out of bounds texture index specified!");
-                }
+        if point.texture_index > 0 {
+            out.extend_from_slice(&textures[point.texture_index - 1]);
+        } else {
+          out.push(0.0);
+          out.push(0.0);
+        }
+
+        index
+    };
 
-                out.push(textures[point.texture_index - 1].x);
-                out.push(textures[point.texture_index - 1].y);
+    for face in &faces {
+        for point in &face.points {
+            let normal_source = if point.normal_index > 0 {
+                NormalSource::File(point.normal_index)
+            } else if face.smoothing_group == 0 {
+                NormalSource::Flat(face.polygon)
             } else {
-              out.push(0.0);
-              out.push(0.0);
-            }
+                NormalSource::Smooth(face.smoothing_group)
+            };
+
+            indices.push(add_vertex(point, normal_source, &|| {
+                if face.smoothing_group == 0 {
+                    normalize(polygon_normals[face.polygon])
+                } else {
+                    normalize(smooth_normals[&(point.vertex_index, face.smoothing_group)])
+                }
+            }));
         }
     }
 
-    println!("Done!");
+    // Lines and points can share vertices with faces, but only if the file gave them the same
+    // normal.
+    let mut add_loose_vertex = |point: &FacePoint| {
+        let normal_source = if point.normal_index > 0 { NormalSource::File(point.normal_index) } else { NormalSource::Missing };
+        add_vertex(point, normal_source, &|| [0.0; 3])
+    };
+    let line_indices: Vec<u32> = line_segments.iter().flat_map(|segment| segment.iter()).map(&mut add_loose_vertex).collect();
+    let point_indices: Vec<u32> = loose_points.iter().map(&mut add_loose_vertex).collect();
+
+    log!("Done!");
 
-    Model {
+    log!("{} unique vertices for {} indices.", out.len() / layout.stride, indices.len());
+    if !line_indices.is_empty() || !point_indices.is_empty() {
+        log!("{} line segments and {} points.", line_indices.len() / 2, point_indices.len());
+    }
+
+    Ok(Model {
         vertices: out,
+        indices,
+        layout,
         materials: model_materials,
-        material_indices: material_indices
+        submeshes,
+        objects,
+        line_indices,
+        point_indices,
+        missing_normals,
+        missing_texture_coordinates,
+        sources,
+        bounds: Bounds::empty(),
+        bounding_sphere: BoundingSphere::empty()
+    })
+}
+
+/// Geometry from formats that don't have materials, like PLY and STL. Everything is indexed by
+/// position, and the optional attributes have one entry per position if they're there.
+struct TriangleMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Option<Vec<[f32; 3]>>,
+    texture_coordinates: Option<Vec<[f32; 2]>>,
+    colors: Option<Vec<[f32; 4]>>,
+    triangles: Vec<[u32; 3]>,
+    /// Named runs of `triangles`.
+    objects: Vec<(String, Range<usize>)>
+}
+
+impl TriangleMesh {
+    /// Interleaves everything into a model with a single plain material. Missing normals are
+    /// smoothed across every triangle that shares a position.
+    fn into_model(self, source: &Path) -> Model {
+        let corner_count = self.triangles.len() * 3;
+        let normals_missing = self.normals.is_none();
+        let normals = match self.normals {
+            Some(normals) => normals,
+            None => smooth_normals(&self.positions, &self.triangles)
+        };
+
+        let mut layout = VertexLayout::standard();
+        if self.colors.is_some() {
+            layout = layout.with(AttributeKind::Color, 4);
+        }
+
+        let mut vertices: Vec<f32> = Vec::with_capacity(self.positions.len() * layout.stride);
+        for index in 0..self.positions.len() {
+            vertices.extend_from_slice(&self.positions[index]);
+            vertices.extend_from_slice(&normals[index]);
+
+            match &self.texture_coordinates {
+                Some(coordinates) => vertices.extend_from_slice(&coordinates[index]),
+                None => vertices.extend_from_slice(&[0.0, 0.0])
+            }
+
+            if let Some(colors) = &self.colors {
+                vertices.extend_from_slice(&colors[index]);
+            }
+        }
+
+        let indices: Vec<u32> = self.triangles.iter().flat_map(|triangle| triangle.iter().cloned()).collect();
+
+        let objects = self.objects.into_iter()
+            .filter(|(_, triangles)| triangles.end > triangles.start)
+            .map(|(name, triangles)| Object {
+                name,
+                start: triangles.start * 3,
+                count: (triangles.end - triangles.start) * 3,
+                submeshes: 0..1,
+                groups: vec![]
+            })
+            .collect();
+
+        Model {
+            vertices,
+            submeshes: vec![Submesh {
+                material_index: 0,
+                start: 0,
+                count: indices.len(),
+                bounds: Bounds::empty(),
+                bounding_sphere: BoundingSphere::empty()
+            }],
+            indices,
+            layout,
+            materials: vec![plain_material()],
+            objects,
+            line_indices: vec![],
+            point_indices: vec![],
+            missing_normals: if normals_missing { corner_count } else { 0 },
+            missing_texture_coordinates: if self.texture_coordinates.is_none() { corner_count } else { 0 },
+            sources: vec![source.to_path_buf()],
+            bounds: Bounds::empty(),
+            bounding_sphere: BoundingSphere::empty()
+        }
+    }
+}
+
+/// A light grey material for formats that don't have any.
+fn plain_material() -> Material {
+    let mut material = Material::new();
+    material.name = String::from("default");
+    material.ambient_percentage = vec![1.0, 1.0, 1.0];
+    material.diffuse_color = vec![0.8, 0.8, 0.8];
+    material.specular_color = vec![0.2, 0.2, 0.2];
+    material.emissive_color = vec![0.0, 0.0, 0.0];
+    material.shininess = 32.0;
+
+    material
+}
+
+/// Averages the normals of every triangle touching each position, weighted by area.
+fn smooth_normals(positions: &[[f32; 3]], triangles: &[[u32; 3]]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0.0; 3]; positions.len()];
+
+    for triangle in triangles {
+        let [a, b, c] = [positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]];
+        let normal = cross([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+
+        for &index in triangle {
+            for axis in 0..3 {
+                normals[index as usize][axis] += normal[axis];
+            }
+        }
+    }
+
+    normals.into_iter().map(normalize).collect()
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+/// Scales a vector to unit length. Zero-length vectors (from degenerate faces) are left alone.
+fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = (vector[0] * vector[0] + vector[1] * vector[1] + vector[2] * vector[2]).sqrt();
+
+    if length == 0.0 {
+        return vector;
+    }
+
+    [vector[0] / length, vector[1] / length, vector[2] / length]
+}
+
+/// Ends the last submesh at `end`. If nothing was drawn with it, it's dropped instead.
+fn close_submesh(submeshes: &mut Vec<Submesh>, end: usize) {
+    if let Some(submesh) = submeshes.last_mut() {
+        submesh.count = end - submesh.start;
+
+        if submesh.count == 0 {
+            submeshes.pop();
+        }
     }
 }
 
+/// Ends the last group of `object`, dropping it if it's empty. `submesh_end` should be taken
+/// after the current submesh has been closed.
+fn close_group(object: &mut Object, end: usize, submesh_end: usize) {
+    if let Some(group) = object.groups.last_mut() {
+        group.count = end - group.start;
+        group.submeshes.end = submesh_end;
+
+        if group.count == 0 {
+            object.groups.pop();
+        }
+    }
+}
+
+/// Ends the last object and its last group, dropping the object if it's empty.
+fn close_object(objects: &mut Vec<Object>, end: usize, submesh_end: usize) {
+    if let Some(object) = objects.last_mut() {
+        close_group(object, end, submesh_end);
+        object.count = end - object.start;
+        object.submeshes.end = submesh_end;
+
+        if object.count == 0 {
+            objects.pop();
+        }
+    }
+}
+
+/// Checks a one-based face index refers to something that's been read, where `count` is how
+/// many have been. Negative indices count back from the most recently read element, so -1 is the
+/// last one. The location is only worked out if something's wrong.
+fn resolve_index(index: isize, kind: &'static str, count: usize, location: impl Fn() -> Location) -> Result<usize, ModelError> {
+    let resolved = if index < 0 { count as isize + index + 1 } else { index };
+
+    if index == 0 || resolved < 1 || resolved > count as isize {
+        return Err(ModelError::IndexOutOfBounds { location: location(), kind, index, count });
+    }
+
+    Ok(resolved as usize)
+}
+
+
+/// Splits a polygon into triangles by ear clipping, so concave faces come out right as well as
+/// convex ones. The triangles are returned as indices into `positions`, wound the same way as the
+/// polygon.
+fn triangulate(positions: &[[f32; 3]]) -> Vec<[usize; 3]> {
+    if positions.len() == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    // Work out which way the polygon faces (Newell's method), then flatten it onto whichever axis
+    // plane it's most parallel to.
+    let mut normal = [0.0f32; 3];
+    for i in 0..positions.len() {
+        let current = positions[i];
+        let next = positions[(i + 1) % positions.len()];
+
+        normal[0] += (current[1] - next[1]) * (current[2] + next[2]);
+        normal[1] += (current[2] - next[2]) * (current[0] + next[0]);
+        normal[2] += (current[0] - next[0]) * (current[1] + next[1]);
+    }
+
+    let (u_axis, v_axis, facing) = if normal[0].abs() >= normal[1].abs() && normal[0].abs() >= normal[2].abs() {
+        (1, 2, normal[0])
+    } else if normal[1].abs() >= normal[2].abs() {
+        (2, 0, normal[1])
+    } else {
+        (0, 1, normal[2])
+    };
+
+    // Flip the flattened polygon if needed so it's always counter-clockwise.
+    let sign = if facing < 0.0 { -1.0 } else { 1.0 };
+    let points: Vec<[f32; 2]> = positions.iter().map(|position| {
+        [position[u_axis], position[v_axis] * sign]
+    }).collect();
+
+    let cross = |a: [f32; 2], b: [f32; 2], c: [f32; 2]| {
+        (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+    };
+
+    let mut remaining: Vec<usize> = (0..positions.len()).collect();
+    let mut triangles: Vec<[usize; 3]> = vec![];
+
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let mut ear = None;
+
+        for i in 0..count {
+            let previous = remaining[(i + count - 1) % count];
+            let current = remaining[i];
+            let next = remaining[(i + 1) % count];
+            let (a, b, c) = (points[previous], points[current], points[next]);
+
+            // Reflex corners can't be ears.
+            if cross(a, b, c) <= 0.0 {
+                continue;
+            }
+
+            let blocked = remaining.iter().any(|&other| {
+                if other == previous || other == current || other == next {
+                    return false;
+                }
+
+                let p = points[other];
+                cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+            });
+
+            if !blocked {
+                ear = Some(i);
+                break;
+            }
+        }
+
+        match ear {
+            Some(i) => {
+                triangles.push([
+                    remaining[(i + count - 1) % count],
+                    remaining[i],
+                    remaining[(i + 1) % count]
+                ]);
+                remaining.remove(i);
+            }
+            None => {
+                // Degenerate or self-intersecting, so there's no right answer. Fan out what's
+                // left rather than dropping it.
+                for i in 1..count - 1 {
+                    triangles.push([remaining[0], remaining[i], remaining[i + 1]]);
+                }
+                return triangles;
+            }
+        }
+    }
+
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
 
 enum MaterialLineType {
     NewMaterial,
@@ -261,44 +1201,140 @@ enum MaterialLineType {
     DiffuseColor,
     SpecularColor,
     EmissiveColor,
+    TransmissionFilter,
     Shininess,
+    OpticalDensity,
+    Dissolve,
+    Transparency,
+    IlluminationModel,
+    AmbientMap,
+    DiffuseMap,
+    SpecularMap,
+    EmissiveMap,
+    ShininessMap,
+    DissolveMap,
+    BumpMap,
+    DisplacementMap,
     Comment,
     Unknown
 }
 
+/// A texture referenced by a material, along with the options it was given in the MTL file.
+#[derive(Debug, Clone)]
+pub struct TextureMap {
+    /// Resolved relative to the file the map came from. For embedded images this is just a
+    /// unique name, and the image itself is in `data`.
+    pub path: PathBuf,
+    /// The encoded image (PNG, JPEG, ...) when it was embedded in the model file.
+    pub data: Option<Vec<u8>>,
+    pub offset: [f32; 3],
+    pub scale: [f32; 3],
+    pub turbulence: [f32; 3],
+    pub bump_multiplier: f32,
+    pub clamp: bool,
+    pub blend_u: bool,
+    pub blend_v: bool
+}
+
+impl TextureMap {
+    fn new(path: PathBuf) -> TextureMap {
+        TextureMap {
+            path,
+            data: None,
+            offset: [0.0, 0.0, 0.0],
+            scale: [1.0, 1.0, 1.0],
+            turbulence: [0.0, 0.0, 0.0],
+            bump_multiplier: 1.0,
+            clamp: false,
+            blend_u: true,
+            blend_v: true
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Material {
+    /// What `newmtl` called it, or the name the glTF gave it.
+    pub name: String,
     pub ambient_percentage: Vec<f32>,
     pub diffuse_color: Vec<f32>,
     pub specular_color: Vec<f32>,
     pub emissive_color: Vec<f32>,
-    pub shininess: f32
+    pub transmission_filter: Vec<f32>,
+    pub shininess: f32,
+    pub optical_density: f32,
+    /// 1.0 is fully opaque.
+    pub dissolve: f32,
+    pub illumination_model: u32,
+    /// Only glTF models have these, OBJ materials get 0 and 1.
+    pub metallic: f32,
+    pub roughness: f32,
+
+    pub ambient_map: Option<TextureMap>,
+    pub diffuse_map: Option<TextureMap>,
+    pub specular_map: Option<TextureMap>,
+    pub emissive_map: Option<TextureMap>,
+    pub shininess_map: Option<TextureMap>,
+    pub dissolve_map: Option<TextureMap>,
+    pub bump_map: Option<TextureMap>,
+    pub displacement_map: Option<TextureMap>,
+    pub metallic_roughness_map: Option<TextureMap>,
+    pub occlusion_map: Option<TextureMap>
+}
+
+impl Material {
+    fn new() -> Material {
+        Material {
+            name: String::new(),
+            ambient_percentage: vec![],
+            diffuse_color: vec![],
+            specular_color: vec![],
+            emissive_color: vec![],
+            transmission_filter: vec![],
+            shininess: 0.0,
+            optical_density: 1.0,
+            dissolve: 1.0,
+            illumination_model: 2,
+            metallic: 0.0,
+            roughness: 1.0,
+
+            ambient_map: None,
+            diffuse_map: None,
+            specular_map: None,
+            emissive_map: None,
+            shininess_map: None,
+            dissolve_map: None,
+            bump_map: None,
+            displacement_map: None,
+            metallic_roughness_map: None,
+            occlusion_map: None
+        }
+    }
 }
 
-fn load_material_file(filepath: &PathBuf) -> HashMap<String, Material> {
-    println!("Reading file {:?}...", filepath);
-    let material_file = File::open(filepath).expect("Couldn't open material file!");
+/// Reads an MTL file from anywhere, like memory. `filepath` is only used for error messages and
+/// to work out where texture maps are.
+pub fn read_material_file<R: BufRead>(buffer: R, filepath: &Path) -> Result<HashMap<String, Material>, ModelError> {
+    read_material_file_with(buffer, filepath, &FileResolver)
+}
 
-    let buffer = BufReader::new(material_file);
+/// Like `read_material_file`, but texture maps are found by `resolver`.
+pub fn read_material_file_with<R: BufRead>(buffer: R, filepath: &Path, resolver: &dyn Resolver) -> Result<HashMap<String, Material>, ModelError> {
     let mut current_material_name = String::from("");
-    let mut current_material = Material {
-        ambient_percentage: vec![],
-        diffuse_color: vec![],
-        specular_color: vec![],
-        emissive_color: vec![],
-        shininess: 0.0
-    };
+    let mut current_material = Material::new();
 
     let mut materials = HashMap::new();
 
-    'lines: for line in buffer.lines() {
-        let good_line = line.expect("Couldn't read the line!");
-        let mut tokens = good_line.split_whitespace();
+    'lines: for (line_index, line) in buffer.lines().enumerate() {
+        let good_line = line.map_err(|error| ModelError::Io { path: filepath.to_path_buf(), error })?;
+        let mut tokens = Tokens::new(filepath, line_index + 1, &good_line);
 
         let first_token = tokens.next();
 
         let line_type = match first_token {
             Some(token) => {
+                let token = token.text;
+
                 if token == "newmtl" {
                     MaterialLineType::NewMaterial
                 } else if token == "Ka" {
@@ -309,8 +1345,34 @@ fn load_material_file(filepath: &PathBuf) -> HashMap<String, Material> {
                     MaterialLineType::SpecularColor
                 } else if token == "Ke" {
                     MaterialLineType::EmissiveColor
+                } else if token == "Tf" {
+                    MaterialLineType::TransmissionFilter
                 } else if token == "Ns" {
                     MaterialLineType::Shininess
+                } else if token == "Ni" {
+                    MaterialLineType::OpticalDensity
+                } else if token == "d" {
+                    MaterialLineType::Dissolve
+                } else if token == "Tr" {
+                    MaterialLineType::Transparency
+                } else if token == "illum" {
+                    MaterialLineType::IlluminationModel
+                } else if token == "map_Ka" {
+                    MaterialLineType::AmbientMap
+                } else if token == "map_Kd" {
+                    MaterialLineType::DiffuseMap
+                } else if token == "map_Ks" {
+                    MaterialLineType::SpecularMap
+                } else if token == "map_Ke" {
+                    MaterialLineType::EmissiveMap
+                } else if token == "map_Ns" {
+                    MaterialLineType::ShininessMap
+                } else if token == "map_d" {
+                    MaterialLineType::DissolveMap
+                } else if token == "map_Bump" || token == "map_bump" || token == "bump" {
+                    MaterialLineType::BumpMap
+                } else if token == "disp" {
+                    MaterialLineType::DisplacementMap
                 } else if token == "#" {
                     MaterialLineType::Comment
                 } else {
@@ -322,51 +1384,111 @@ fn load_material_file(filepath: &PathBuf) -> HashMap<String, Material> {
 
         match line_type {
             MaterialLineType::NewMaterial => {
-                println!("Found a new material!");
+                log!("Found a new material!");
 
                 if current_material_name != "" {
-                    println!("Adding previous material to hashmap.");
+                    log!("Adding previous material to hashmap.");
                     materials.insert(current_material_name, current_material);
                 }
 
-                current_material_name = String::from(tokens.next().unwrap());
-                println!("Material name is \"{}\"", current_material_name);
-                current_material = Material {
-                    ambient_percentage: vec![],
-                    diffuse_color: vec![],
-                    specular_color: vec![],
-                    emissive_color: vec![],
-                    shininess: 0.0
-                };
+                current_material_name = String::from(tokens.next_argument("newmtl")?.text);
+                log!("Material name is \"{}\"", current_material_name);
+                current_material = Material::new();
+                current_material.name = current_material_name.clone();
             }
             MaterialLineType::AmbientPercentage => {
-                println!("Found an ambient percentage!");
-                current_material.ambient_percentage = parse_numbers(tokens);
-                println!("Ambient percentage was {:?}", current_material.ambient_percentage);
+                log!("Found an ambient percentage!");
+                current_material.ambient_percentage = parse_numbers(tokens, 3)?;
+                log!("Ambient percentage was {:?}", current_material.ambient_percentage);
             }
             MaterialLineType::DiffuseColor => {
-                println!("Found a diffuse color!");
-                current_material.diffuse_color = parse_numbers(tokens);
-                println!("Diffuse collor was {:?}", current_material.diffuse_color);
+                log!("Found a diffuse color!");
+                current_material.diffuse_color = parse_numbers(tokens, 3)?;
+                log!("Diffuse collor was {:?}", current_material.diffuse_color);
             }
             MaterialLineType::SpecularColor => {
-                println!("Found a specular color!");
-                current_material.specular_color = parse_numbers(tokens);
-                println!("Specular color was {:?}", current_material.specular_color);
+                log!("Found a specular color!");
+                current_material.specular_color = parse_numbers(tokens, 3)?;
+                log!("Specular color was {:?}", current_material.specular_color);
             }
             MaterialLineType::EmissiveColor => {
-                println!("Found an emissive color!");
-                current_material.emissive_color = parse_numbers(tokens);
-                println!("Emissive color was {:?}", current_material.emissive_color);
+                log!("Found an emissive color!");
+                current_material.emissive_color = parse_numbers(tokens, 3)?;
+                log!("Emissive color was {:?}", current_material.emissive_color);
+            }
+            MaterialLineType::TransmissionFilter => {
+                log!("Found a transmission filter!");
+                current_material.transmission_filter = parse_numbers(tokens, 3)?;
+                log!("Transmission filter was {:?}", current_material.transmission_filter);
             }
             MaterialLineType::Shininess => {
-                println!("Found a shininess!");
-                current_material.shininess = parse_numbers(tokens)[0];
-                println!("Shininess was {}", current_material.shininess);
+                log!("Found a shininess!");
+                current_material.shininess = parse_numbers(tokens, 1)?[0];
+                log!("Shininess was {}", current_material.shininess);
+            }
+            MaterialLineType::OpticalDensity => {
+                log!("Found an optical density!");
+                current_material.optical_density = parse_numbers(tokens, 1)?[0];
+                log!("Optical density was {}", current_material.optical_density);
+            }
+            MaterialLineType::Dissolve => {
+                log!("Found a dissolve!");
+                current_material.dissolve = parse_dissolve(tokens)?;
+                log!("Dissolve was {}", current_material.dissolve);
+            }
+            MaterialLineType::Transparency => {
+                // Tr is just the inverse of d.
+                log!("Found a transparency!");
+                current_material.dissolve = 1.0 - parse_numbers(tokens, 1)?[0];
+                log!("Dissolve was {}", current_material.dissolve);
+            }
+            MaterialLineType::IlluminationModel => {
+                log!("Found an illumination model!");
+                let model = tokens.next_argument("illum")?;
+                current_material.illumination_model = match model.text.parse() {
+                    Ok(number) => number,
+                    Err(_) => return Err(ModelError::InvalidNumber {
+                        location: tokens.location(model.column),
+                        token: String::from(model.text)
+                    })
+                };
+                log!("Illumination model was {}", current_material.illumination_model);
+            }
+            MaterialLineType::AmbientMap => {
+                current_material.ambient_map = Some(parse_texture_map(tokens, "map_Ka", filepath, resolver)?);
+                log!("Ambient map was {:?}", current_material.ambient_map);
+            }
+            MaterialLineType::DiffuseMap => {
+                current_material.diffuse_map = Some(parse_texture_map(tokens, "map_Kd", filepath, resolver)?);
+                log!("Diffuse map was {:?}", current_material.diffuse_map);
+            }
+            MaterialLineType::SpecularMap => {
+                current_material.specular_map = Some(parse_texture_map(tokens, "map_Ks", filepath, resolver)?);
+                log!("Specular map was {:?}", current_material.specular_map);
+            }
+            MaterialLineType::EmissiveMap => {
+                current_material.emissive_map = Some(parse_texture_map(tokens, "map_Ke", filepath, resolver)?);
+                log!("Emissive map was {:?}", current_material.emissive_map);
+            }
+            MaterialLineType::ShininessMap => {
+                current_material.shininess_map = Some(parse_texture_map(tokens, "map_Ns", filepath, resolver)?);
+                log!("Shininess map was {:?}", current_material.shininess_map);
+            }
+            MaterialLineType::DissolveMap => {
+                current_material.dissolve_map = Some(parse_texture_map(tokens, "map_d", filepath, resolver)?);
+                log!("Dissolve map was {:?}", current_material.dissolve_map);
+            }
+            MaterialLineType::BumpMap => {
+                current_material.bump_map = Some(parse_texture_map(tokens, "map_Bump", filepath, resolver)?);
+                log!("Bump map was {:?}", current_material.bump_map);
+            }
+            MaterialLineType::DisplacementMap => {
+                current_material.displacement_map = Some(parse_texture_map(tokens, "disp", filepath, resolver)?);
+                log!("Displacement map was {:?}", current_material.displacement_map);
             }
 
             MaterialLineType::Unknown => {
-                println!("Unknown line type: {}", good_line);
+                log!("Unknown line type: {}", good_line);
             }
             MaterialLineType::Comment => ()
         }
@@ -374,19 +1496,165 @@ fn load_material_file(filepath: &PathBuf) -> HashMap<String, Material> {
 
     materials.insert(current_material_name, current_material);
 
-    println!("Materials: {:?}", materials);
+    log!("Materials: {:?}", materials);
+
+    Ok(materials)
+}
+
+
+/// Parses a `d` line. Some exporters write `d -halo 0.5`; the halo flag doesn't mean anything
+/// to us, so it's skipped.
+fn parse_dissolve(mut tokens: Tokens) -> Result<f32, ModelError> {
+    if let Some(token) = tokens.peek() {
+        if token.text == "-halo" {
+            tokens.next();
+        }
+    }
+
+    Ok(parse_numbers(tokens, 1)?[0])
+}
+
+/// Parses a `map_*` line: any number of options, then the file name, which can contain spaces.
+fn parse_texture_map(mut tokens: Tokens, keyword: &str, material_path: &Path, resolver: &dyn Resolver) -> Result<TextureMap, ModelError> {
+    let mut map = TextureMap::new(PathBuf::new());
+
+    loop {
+        let token = tokens.next_argument(keyword)?;
+
+        match token.text {
+            "-o" => map.offset = parse_option_vector(&mut tokens, token.text, map.offset)?,
+            "-s" => map.scale = parse_option_vector(&mut tokens, token.text, map.scale)?,
+            "-t" => map.turbulence = parse_option_vector(&mut tokens, token.text, map.turbulence)?,
+            "-bm" => map.bump_multiplier = parse_option_number(&mut tokens, token.text)?,
+            "-clamp" => map.clamp = parse_option_switch(&mut tokens, token.text)?,
+            "-blendu" => map.blend_u = parse_option_switch(&mut tokens, token.text)?,
+            "-blendv" => map.blend_v = parse_option_switch(&mut tokens, token.text)?,
+
+            // These are understood but don't change anything we render.
+            "-cc" => { parse_option_switch(&mut tokens, token.text)?; }
+            "-boost" | "-texres" => { parse_option_number(&mut tokens, token.text)?; }
+            "-mm" => {
+                parse_option_number(&mut tokens, token.text)?;
+                parse_option_number(&mut tokens, token.text)?;
+            }
+            "-imfchan" | "-type" => { tokens.next_argument(token.text)?; }
+
+            _ => {
+                // Exporters on Windows like to use backslashes.
+                let filename = tokens.remainder(&token).replace('\\', "/");
+                map.path = resolver.resolve(&filename, material_path);
+
+                // A texture that can't be found isn't worth failing the whole model over.
+                match resolver.load_texture(&map.path) {
+                    Some(Ok(data)) => map.data = Some(data),
+                    Some(Err(error)) => log!("Couldn't load texture {:?}: {}", map.path, error),
+                    None => ()
+                }
+
+                return Ok(map);
+            }
+        }
+    }
+}
+
+fn parse_option_number(tokens: &mut Tokens, option: &str) -> Result<f32, ModelError> {
+    let token = tokens.next_argument(option)?;
 
-    materials
+    match token.text.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(ModelError::InvalidNumber {
+            location: tokens.location(token.column),
+            token: String::from(token.text)
+        })
+    }
+}
+
+/// Parses `u [v [w]]`. Anything left out keeps its value from `defaults`.
+fn parse_option_vector(tokens: &mut Tokens, option: &str, defaults: [f32; 3]) -> Result<[f32; 3], ModelError> {
+    let mut vector = defaults;
+    vector[0] = parse_option_number(tokens, option)?;
+
+    for component in vector.iter_mut().skip(1) {
+        match tokens.peek().and_then(|token| token.text.parse().ok()) {
+            Some(value) => {
+                *component = value;
+                tokens.next();
+            }
+            None => break
+        }
+    }
+
+    Ok(vector)
 }
 
+fn parse_option_switch(tokens: &mut Tokens, option: &str) -> Result<bool, ModelError> {
+    let token = tokens.next_argument(option)?;
+
+    match token.text {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ModelError::InvalidOption {
+            location: tokens.location(token.column),
+            option: String::from(option),
+            value: String::from(token.text)
+        })
+    }
+}
 
-fn parse_numbers(tokens: std::str::SplitWhitespace) -> Vec<f32> {
+/// Parses the rest of a line as numbers, making sure there are at least `minimum` of them.
+fn parse_numbers(mut tokens: Tokens, minimum: usize) -> Result<Vec<f32>, ModelError> {
     let mut numbers: Vec<f32> = vec![];
 
-    for token in tokens {
-        let value: f32 = token.parse().unwrap();
+    while let Some(token) = tokens.next() {
+        let value: f32 = match token.text.parse() {
+            Ok(value) => value,
+            Err(_) => return Err(ModelError::InvalidNumber {
+                location: tokens.location(token.column),
+                token: String::from(token.text)
+            })
+        };
         numbers.push(value);
     }
 
-    numbers
+    if numbers.len() < minimum {
+        return Err(ModelError::NotEnoughNumbers {
+            location: tokens.end_location(),
+            expected: minimum,
+            found: numbers.len()
+        });
+    }
+
+    Ok(numbers)
+}
+
+/// Like `parse_numbers`, but fills `numbers` instead of allocating, so it's cheap enough to call
+/// for every line of a big file. There have to be at least as many numbers as `numbers` has room
+/// for. Extra ones are still checked, but thrown away.
+fn parse_vector(mut tokens: Tokens, numbers: &mut [f32]) -> Result<(), ModelError> {
+    let mut found = 0;
+
+    while let Some(token) = tokens.next() {
+        let value: f32 = match token.text.parse() {
+            Ok(value) => value,
+            Err(_) => return Err(ModelError::InvalidNumber {
+                location: tokens.location(token.column),
+                token: String::from(token.text)
+            })
+        };
+
+        if found < numbers.len() {
+            numbers[found] = value;
+        }
+        found += 1;
+    }
+
+    if found < numbers.len() {
+        return Err(ModelError::NotEnoughNumbers {
+            location: tokens.end_location(),
+            expected: numbers.len(),
+            found
+        });
+    }
+
+    Ok(())
 }
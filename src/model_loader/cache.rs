@@ -0,0 +1,543 @@
+use std::fs::{self, File};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use super::{AttributeKind, BoundingSphere, Bounds, Group, Material, Model, ModelError, Object, Submesh, TextureMap, VertexAttribute, VertexLayout};
+
+
+const MAGIC: &[u8; 8] = b"MESHCACH";
+/// Bump this whenever the layout below changes, so old caches get rebuilt instead of misread.
+const VERSION: u32 = 5;
+/// The magic, the version and the checksum.
+const HEADER_SIZE: usize = 8 + 4 + 8;
+
+
+/// Where the cache for a model file lives: right next to it, with `.meshcache` on the end.
+pub fn cache_path(source: &Path) -> PathBuf {
+    let mut path = source.as_os_str().to_os_string();
+    path.push(".meshcache");
+    PathBuf::from(path)
+}
+
+/// Writes a model to a cache file. The files in `Model::sources` are stamped with their current
+/// modification times, so the cache can tell when it's out of date.
+///
+/// Everything is little endian. After the header comes the sources, the vertex layout, the vertex
+/// blob, the triangle, line and point index blobs, the missing attribute counts, the submeshes,
+/// the objects and finally the materials.
+pub fn save_cache(model: &Model, path: &Path) -> Result<(), ModelError> {
+    let io_error = |error| ModelError::Io { path: path.to_path_buf(), error };
+
+    let mut body = Writer { bytes: vec![] };
+
+    body.u32(model.sources.len() as u32);
+    for source in &model.sources {
+        let modified = fs::metadata(source).and_then(|metadata| metadata.modified())
+            .map_err(|error| ModelError::Io { path: source.clone(), error })?;
+
+        body.path(source);
+        body.time(modified);
+    }
+
+    body.u32(model.layout.stride as u32);
+    body.u32(model.layout.attributes.len() as u32);
+    for attribute in &model.layout.attributes {
+        body.u8(attribute_kind_id(attribute.kind));
+        body.u32(attribute.size as u32);
+        body.u32(attribute.offset as u32);
+    }
+
+    body.floats(&model.vertices);
+    body.indices(&model.indices);
+    body.indices(&model.line_indices);
+    body.indices(&model.point_indices);
+    body.u64(model.missing_normals as u64);
+    body.u64(model.missing_texture_coordinates as u64);
+
+    body.u32(model.submeshes.len() as u32);
+    for submesh in &model.submeshes {
+        body.u32(submesh.material_index as u32);
+        body.u64(submesh.start as u64);
+        body.u64(submesh.count as u64);
+    }
+
+    body.u32(model.objects.len() as u32);
+    for object in &model.objects {
+        body.string(&object.name);
+        body.u64(object.start as u64);
+        body.u64(object.count as u64);
+        body.range(&object.submeshes);
+
+        body.u32(object.groups.len() as u32);
+        for group in &object.groups {
+            body.u32(group.names.len() as u32);
+            for name in &group.names {
+                body.string(name);
+            }
+            body.u64(group.start as u64);
+            body.u64(group.count as u64);
+            body.range(&group.submeshes);
+        }
+    }
+
+    body.u32(model.materials.len() as u32);
+    for material in &model.materials {
+        body.material(material);
+    }
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + body.bytes.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&checksum(&body.bytes).to_le_bytes());
+    bytes.extend_from_slice(&body.bytes);
+
+    // Write somewhere else first, so a crash halfway through can't leave a broken cache behind.
+    let mut temporary_path = path.as_os_str().to_os_string();
+    temporary_path.push(".tmp");
+    fs::write(&temporary_path, &bytes).map_err(io_error)?;
+    fs::rename(&temporary_path, path).map_err(io_error)?;
+
+    log!("Wrote {} bytes to the cache {:?}.", bytes.len(), path);
+
+    Ok(())
+}
+
+/// Memory-maps a cache file and reads the model back out of it. Returns `None` if there's no
+/// cache, it's from another version, or one of its sources has been changed since it was written.
+pub fn load_cache(path: &Path) -> Result<Option<Model>, ModelError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(None)
+    };
+
+    // Nothing else should be writing to the cache while it's mapped, since a new cache is always
+    // renamed over the old one.
+    let map = unsafe { Mmap::map(&file) }.map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+    let malformed = |reason: String| ModelError::Malformed { path: path.to_path_buf(), reason };
+
+    if map.len() < HEADER_SIZE || &map[0..8] != MAGIC {
+        return Err(malformed(String::from("this isn't a mesh cache")));
+    }
+
+    let version = u32::from_le_bytes([map[8], map[9], map[10], map[11]]);
+    if version != VERSION {
+        log!("The cache {:?} is version {}, but this is version {}.", path, version, VERSION);
+        return Ok(None);
+    }
+
+    let mut stored_checksum = [0; 8];
+    stored_checksum.copy_from_slice(&map[12..HEADER_SIZE]);
+    if u64::from_le_bytes(stored_checksum) != checksum(&map[HEADER_SIZE..]) {
+        return Err(malformed(String::from("the checksum doesn't match")));
+    }
+
+    let mut body = Reader { bytes: &map[HEADER_SIZE..], offset: 0 };
+
+    let mut sources = vec![];
+    for _ in 0..body.u32().map_err(malformed)? {
+        let source = body.path().map_err(malformed)?;
+        let modified = body.time().map_err(malformed)?;
+
+        let current = fs::metadata(&source).and_then(|metadata| metadata.modified());
+        match current {
+            Ok(current) if current == modified => sources.push(source),
+            _ => {
+                log!("{:?} changed since the cache {:?} was written.", source, path);
+                return Ok(None);
+            }
+        }
+    }
+
+    read_model(&mut body, sources).map(Some).map_err(malformed)
+}
+
+
+fn read_model(body: &mut Reader, sources: Vec<PathBuf>) -> Result<Model, String> {
+    let stride = body.u32()? as usize;
+    let mut attributes = vec![];
+    for _ in 0..body.u32()? {
+        let kind = attribute_kind_from_id(body.u8()?)?;
+        let size = body.u32()? as usize;
+        let offset = body.u32()? as usize;
+
+        if offset + size > stride {
+            return Err(format!("a {:?} attribute doesn't fit in a vertex", kind));
+        }
+        attributes.push(VertexAttribute { kind, size, offset });
+    }
+
+    let vertices = body.floats()?;
+    let indices = body.indices()?;
+    let line_indices = body.indices()?;
+    let point_indices = body.indices()?;
+    let missing_normals = body.u64()? as usize;
+    let missing_texture_coordinates = body.u64()? as usize;
+
+    let mut submeshes = vec![];
+    for _ in 0..body.u32()? {
+        submeshes.push(Submesh {
+            material_index: body.u32()? as usize,
+            start: body.u64()? as usize,
+            count: body.u64()? as usize,
+            bounds: Bounds::empty(),
+            bounding_sphere: BoundingSphere::empty()
+        });
+    }
+
+    let mut objects = vec![];
+    for _ in 0..body.u32()? {
+        let name = body.string()?;
+        let start = body.u64()? as usize;
+        let count = body.u64()? as usize;
+        let object_submeshes = body.range()?;
+
+        let mut groups = vec![];
+        for _ in 0..body.u32()? {
+            let mut names = vec![];
+            for _ in 0..body.u32()? {
+                names.push(body.string()?);
+            }
+
+            groups.push(Group {
+                names,
+                start: body.u64()? as usize,
+                count: body.u64()? as usize,
+                submeshes: body.range()?
+            });
+        }
+
+        objects.push(Object { name, start, count, submeshes: object_submeshes, groups });
+    }
+
+    let mut materials = vec![];
+    for _ in 0..body.u32()? {
+        materials.push(body.material()?);
+    }
+
+    let vertex_count = vertices.len().checked_div(stride).unwrap_or(0);
+    if indices.iter().chain(&line_indices).chain(&point_indices).any(|&index| index as usize >= vertex_count) {
+        return Err(String::from("an index points past the last vertex"));
+    }
+    if submeshes.iter().any(|submesh| submesh.start.saturating_add(submesh.count) > indices.len()) {
+        return Err(String::from("a submesh goes past the last index"));
+    }
+    if submeshes.iter().any(|submesh| submesh.material_index >= materials.len()) {
+        return Err(String::from("a submesh uses a material that isn't there"));
+    }
+
+    let fits = |start: usize, count: usize, range: &Range<usize>| {
+        start.saturating_add(count) <= indices.len() && range.start <= range.end && range.end <= submeshes.len()
+    };
+    for object in &objects {
+        if !fits(object.start, object.count, &object.submeshes) {
+            return Err(format!("object {:?} goes past the last index or submesh", object.name));
+        }
+        if object.groups.iter().any(|group| !fits(group.start, group.count, &group.submeshes)) {
+            return Err(format!("a group in object {:?} goes past the last index or submesh", object.name));
+        }
+    }
+
+    // The bounds are quick to work out again, so they aren't stored.
+    let mut model = Model {
+        vertices,
+        indices,
+        layout: VertexLayout { attributes, stride },
+        materials,
+        submeshes,
+        objects,
+        line_indices,
+        point_indices,
+        missing_normals,
+        missing_texture_coordinates,
+        sources,
+        bounds: Bounds::empty(),
+        bounding_sphere: BoundingSphere::empty()
+    };
+    model.compute_bounds();
+
+    Ok(model)
+}
+
+fn attribute_kind_id(kind: AttributeKind) -> u8 {
+    match kind {
+        AttributeKind::Position => 0,
+        AttributeKind::Normal => 1,
+        AttributeKind::TextureCoordinate => 2,
+        AttributeKind::Color => 3,
+        AttributeKind::Tangent => 4
+    }
+}
+
+fn attribute_kind_from_id(id: u8) -> Result<AttributeKind, String> {
+    match id {
+        0 => Ok(AttributeKind::Position),
+        1 => Ok(AttributeKind::Normal),
+        2 => Ok(AttributeKind::TextureCoordinate),
+        3 => Ok(AttributeKind::Color),
+        4 => Ok(AttributeKind::Tangent),
+        _ => Err(format!("unknown attribute kind {}", id))
+    }
+}
+
+/// 64-bit FNV-1a. It's only there to catch truncated or corrupted files, not tampering.
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+
+struct Writer {
+    bytes: Vec<u8>
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    /// A length, then the floats.
+    fn floats(&mut self, values: &[f32]) {
+        self.u64(values.len() as u64);
+        for &value in values {
+            self.f32(value);
+        }
+    }
+
+    fn indices(&mut self, indices: &[u32]) {
+        self.u64(indices.len() as u64);
+        for &index in indices {
+            self.u32(index);
+        }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u64(bytes.len() as u64);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn string(&mut self, text: &str) {
+        self.bytes(text.as_bytes());
+    }
+
+    fn path(&mut self, path: &Path) {
+        self.string(&path.to_string_lossy());
+    }
+
+    fn range(&mut self, range: &Range<usize>) {
+        self.u64(range.start as u64);
+        self.u64(range.end as u64);
+    }
+
+    fn time(&mut self, time: SystemTime) {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        self.u64(since_epoch.as_secs());
+        self.u32(since_epoch.subsec_nanos());
+    }
+
+    fn texture_map(&mut self, map: &Option<TextureMap>) {
+        let map = match map {
+            Some(map) => map,
+            None => return self.bool(false)
+        };
+
+        self.bool(true);
+        self.path(&map.path);
+        match &map.data {
+            Some(data) => {
+                self.bool(true);
+                self.bytes(data);
+            }
+            None => self.bool(false)
+        }
+        for &value in map.offset.iter().chain(&map.scale).chain(&map.turbulence) {
+            self.f32(value);
+        }
+        self.f32(map.bump_multiplier);
+        self.bool(map.clamp);
+        self.bool(map.blend_u);
+        self.bool(map.blend_v);
+    }
+
+    fn material(&mut self, material: &Material) {
+        self.string(&material.name);
+        self.floats(&material.ambient_percentage);
+        self.floats(&material.diffuse_color);
+        self.floats(&material.specular_color);
+        self.floats(&material.emissive_color);
+        self.floats(&material.transmission_filter);
+        self.f32(material.shininess);
+        self.f32(material.optical_density);
+        self.f32(material.dissolve);
+        self.u32(material.illumination_model);
+        self.f32(material.metallic);
+        self.f32(material.roughness);
+
+        self.texture_map(&material.ambient_map);
+        self.texture_map(&material.diffuse_map);
+        self.texture_map(&material.specular_map);
+        self.texture_map(&material.emissive_map);
+        self.texture_map(&material.shininess_map);
+        self.texture_map(&material.dissolve_map);
+        self.texture_map(&material.bump_map);
+        self.texture_map(&material.displacement_map);
+        self.texture_map(&material.metallic_roughness_map);
+        self.texture_map(&material.occlusion_map);
+    }
+}
+
+
+/// Reads back what `Writer` wrote. Every read checks it stays inside the file, so a cache that
+/// passes its checksum but is still wrong somehow can't make it panic.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = self.offset.checked_add(length).filter(|&end| end <= self.bytes.len());
+        match end {
+            Some(end) => {
+                let bytes = &self.bytes[self.offset..end];
+                self.offset = end;
+                Ok(bytes)
+            }
+            None => Err(String::from("the cache ends too early"))
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn floats(&mut self) -> Result<Vec<f32>, String> {
+        let count = self.u64()? as usize;
+        Ok(self.take(count.saturating_mul(4))?
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect())
+    }
+
+    fn indices(&mut self) -> Result<Vec<u32>, String> {
+        let count = self.u64()? as usize;
+        Ok(self.take(count.saturating_mul(4))?
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect())
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let length = self.u64()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| String::from("a string isn't valid UTF-8"))
+    }
+
+    fn path(&mut self) -> Result<PathBuf, String> {
+        Ok(PathBuf::from(self.string()?))
+    }
+
+    fn range(&mut self) -> Result<Range<usize>, String> {
+        Ok(self.u64()? as usize..self.u64()? as usize)
+    }
+
+    fn time(&mut self) -> Result<SystemTime, String> {
+        let seconds = self.u64()?;
+        let nanoseconds = self.u32()?;
+        Ok(UNIX_EPOCH + Duration::new(seconds, nanoseconds))
+    }
+
+    fn texture_map(&mut self) -> Result<Option<TextureMap>, String> {
+        if !self.bool()? {
+            return Ok(None);
+        }
+
+        let mut map = TextureMap::new(self.path()?);
+        if self.bool()? {
+            map.data = Some(self.bytes()?.to_vec());
+        }
+        for value in map.offset.iter_mut().chain(&mut map.scale).chain(&mut map.turbulence) {
+            *value = self.f32()?;
+        }
+        map.bump_multiplier = self.f32()?;
+        map.clamp = self.bool()?;
+        map.blend_u = self.bool()?;
+        map.blend_v = self.bool()?;
+
+        Ok(Some(map))
+    }
+
+    fn material(&mut self) -> Result<Material, String> {
+        let mut material = Material::new();
+
+        material.name = self.string()?;
+        material.ambient_percentage = self.floats()?;
+        material.diffuse_color = self.floats()?;
+        material.specular_color = self.floats()?;
+        material.emissive_color = self.floats()?;
+        material.transmission_filter = self.floats()?;
+        material.shininess = self.f32()?;
+        material.optical_density = self.f32()?;
+        material.dissolve = self.f32()?;
+        material.illumination_model = self.u32()?;
+        material.metallic = self.f32()?;
+        material.roughness = self.f32()?;
+
+        material.ambient_map = self.texture_map()?;
+        material.diffuse_map = self.texture_map()?;
+        material.specular_map = self.texture_map()?;
+        material.emissive_map = self.texture_map()?;
+        material.shininess_map = self.texture_map()?;
+        material.dissolve_map = self.texture_map()?;
+        material.bump_map = self.texture_map()?;
+        material.displacement_map = self.texture_map()?;
+        material.metallic_roughness_map = self.texture_map()?;
+        material.occlusion_map = self.texture_map()?;
+
+        Ok(material)
+    }
+}
@@ -0,0 +1,863 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+
+use super::{BoundingSphere, Bounds, Material, Model, ModelError, Object, Submesh, TextureMap, VertexLayout};
+
+
+const GLB_JSON_CHUNK: u32 = 0x4E4F534A;
+const GLB_BINARY_CHUNK: u32 = 0x004E4942;
+
+const MODE_TRIANGLES: u64 = 4;
+const MODE_TRIANGLE_STRIP: u64 = 5;
+const MODE_TRIANGLE_FAN: u64 = 6;
+
+const CLAMP_TO_EDGE: u64 = 33071;
+
+type Matrix = [f32; 16];
+
+const IDENTITY: Matrix = [
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 1.0, 0.0,
+    0.0, 0.0, 0.0, 1.0
+];
+
+
+/// Loads a glTF 2.0 model, either a `.gltf` (with its buffers in separate files or data URIs) or
+/// a binary `.glb`. Node transforms are baked into the vertices, and every node with a mesh
+/// becomes one of the model's objects.
+pub fn load_gltf(path: &Path) -> Result<Model, ModelError> {
+    log!("Reading file {:?}...", path);
+    let bytes = fs::read(path).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    let (json, binary_chunk) = if bytes.starts_with(b"glTF") {
+        split_glb(path, &bytes)?
+    } else {
+        (&bytes[..], None)
+    };
+
+    let json: Value = match serde_json::from_slice(json) {
+        Ok(json) => json,
+        Err(error) => return Err(invalid(path, format!("bad JSON: {}", error)))
+    };
+
+    let version = json.get("asset").and_then(|asset| asset.get("version")).and_then(Value::as_str);
+    if !version.is_some_and(|version| version.starts_with("2.")) {
+        return Err(invalid(path, format!("only glTF 2.0 is supported, this is {:?}", version)));
+    }
+
+    let mut document = Document {
+        path,
+        json: &json,
+        buffers: vec![],
+        data_size: 0
+    };
+    document.buffers = document.load_buffers(binary_chunk)?;
+    document.data_size = bytes.len() + document.buffers.iter().map(Vec::len).sum::<usize>();
+
+    log!("Reading materials...");
+    let mut materials: Vec<Material> = vec![];
+    for index in 0..document.array("materials").len() {
+        materials.push(document.material(index)?);
+    }
+
+    let mut builder = Builder {
+        layout: VertexLayout::standard(),
+        vertices: vec![],
+        indices: vec![],
+        submeshes: vec![],
+        objects: vec![],
+        default_material: None,
+        material_count: materials.len(),
+        missing_normals: 0,
+        missing_texture_coordinates: 0
+    };
+
+    log!("Reading nodes...");
+    let mut visited = vec![false; document.array("nodes").len()];
+    for node_index in document.root_nodes()? {
+        document.visit_node(node_index, IDENTITY, &mut visited, &mut builder)?;
+    }
+
+    // Primitives without a material get the one the spec describes as the default.
+    if builder.default_material.is_some() {
+        materials.push(default_material());
+    }
+
+    log!("{} vertices for {} indices.", builder.vertices.len() / builder.layout.stride, builder.indices.len());
+
+    let mut sources = vec![path.to_path_buf()];
+    for buffer in document.array("buffers") {
+        match buffer.get("uri").and_then(Value::as_str) {
+            Some(uri) if !uri.starts_with("data:") => sources.push(document.resolve(uri)),
+            _ => ()
+        }
+    }
+
+    Ok(Model {
+        vertices: builder.vertices,
+        indices: builder.indices,
+        layout: builder.layout,
+        materials,
+        submeshes: builder.submeshes,
+        objects: builder.objects,
+        line_indices: vec![],
+        point_indices: vec![],
+        missing_normals: builder.missing_normals,
+        missing_texture_coordinates: builder.missing_texture_coordinates,
+        sources,
+        bounds: Bounds::empty(),
+        bounding_sphere: BoundingSphere::empty()
+    })
+}
+
+
+/// Everything a node needs to add its meshes to the model.
+struct Builder {
+    layout: VertexLayout,
+    vertices: Vec<f32>,
+    indices: Vec<u32>,
+    submeshes: Vec<Submesh>,
+    objects: Vec<Object>,
+    default_material: Option<usize>,
+    material_count: usize,
+    missing_normals: usize,
+    missing_texture_coordinates: usize
+}
+
+struct Document<'a> {
+    path: &'a Path,
+    json: &'a Value,
+    buffers: Vec<Vec<u8>>,
+    /// How many bytes the file and its buffers hold, which no accessor can sensibly be bigger
+    /// than, even one that's all zeros.
+    data_size: usize
+}
+
+impl<'a> Document<'a> {
+    /// A top-level array like `meshes`. Missing arrays are treated as empty.
+    fn array(&self, key: &str) -> &'a [Value] {
+        self.json.get(key).and_then(Value::as_array).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    fn element(&self, key: &str, index: usize) -> Result<&'a Value, ModelError> {
+        match self.array(key).get(index) {
+            Some(element) => Ok(element),
+            None => Err(invalid(self.path, format!("{}[{}] doesn't exist", key, index)))
+        }
+    }
+
+    fn load_buffers(&self, binary_chunk: Option<&[u8]>) -> Result<Vec<Vec<u8>>, ModelError> {
+        let mut buffers = vec![];
+
+        for (index, buffer) in self.array("buffers").iter().enumerate() {
+            let data = match buffer.get("uri").and_then(Value::as_str) {
+                Some(uri) => self.load_uri(uri)?,
+                None => match binary_chunk {
+                    // Only the first buffer of a GLB can live in the binary chunk.
+                    Some(chunk) if index == 0 => chunk.to_vec(),
+                    _ => return Err(invalid(self.path, format!("buffers[{}] has no data", index)))
+                }
+            };
+
+            let length = get_usize(buffer, "byteLength").unwrap_or(0);
+            if data.len() < length {
+                return Err(invalid(self.path, format!(
+                    "buffers[{}] should be {} bytes, but only {} were found", index, length, data.len()
+                )));
+            }
+
+            buffers.push(data);
+        }
+
+        Ok(buffers)
+    }
+
+    /// Reads a data URI or a file relative to the model.
+    fn load_uri(&self, uri: &str) -> Result<Vec<u8>, ModelError> {
+        if uri.starts_with("data:") {
+            return match uri.find(";base64,") {
+                Some(start) => match decode_base64(&uri[start + 8..]) {
+                    Some(data) => Ok(data),
+                    None => Err(invalid(self.path, String::from("data URI has invalid base64")))
+                },
+                None => Err(invalid(self.path, String::from("only base64 data URIs are supported")))
+            };
+        }
+
+        let path = self.resolve(uri);
+        fs::read(&path).map_err(|error| ModelError::Io { path, error })
+    }
+
+    fn resolve(&self, uri: &str) -> PathBuf {
+        let filename = decode_percent(uri);
+
+        match self.path.parent() {
+            Some(directory) => directory.join(filename),
+            None => PathBuf::from(filename)
+        }
+    }
+
+    fn buffer_view(&self, index: usize) -> Result<(&[u8], Option<usize>), ModelError> {
+        let view = self.element("bufferViews", index)?;
+        let buffer_index = get_usize(view, "buffer").unwrap_or(0);
+        let buffer = match self.buffers.get(buffer_index) {
+            Some(buffer) => buffer,
+            None => return Err(invalid(self.path, format!("buffers[{}] doesn't exist", buffer_index)))
+        };
+
+        let offset = get_usize(view, "byteOffset").unwrap_or(0);
+        let length = get_usize(view, "byteLength").unwrap_or(0);
+        match buffer.get(offset..offset.saturating_add(length)) {
+            Some(data) => Ok((data, get_usize(view, "byteStride"))),
+            None => Err(invalid(self.path, format!("bufferViews[{}] runs off the end of its buffer", index)))
+        }
+    }
+
+    /// Reads an accessor as floats, whatever its component type. Returns the values along with
+    /// how many components each element has.
+    fn read_floats(&self, index: usize) -> Result<(Vec<f32>, usize), ModelError> {
+        let accessor = self.element("accessors", index)?;
+        let normalized = accessor.get("normalized").and_then(Value::as_bool).unwrap_or(false);
+
+        self.read_accessor(index, |data, component_type| {
+            let value = read_component(data, component_type);
+
+            if !normalized {
+                return value;
+            }
+
+            match component_type {
+                5120 => (value / 127.0).max(-1.0),
+                5121 => value / 255.0,
+                5122 => (value / 32767.0).max(-1.0),
+                5123 => value / 65535.0,
+                _ => value
+            }
+        })
+    }
+
+    fn read_indices(&self, index: usize) -> Result<Vec<u32>, ModelError> {
+        let (values, _) = self.read_accessor(index, |data, component_type| {
+            match component_type {
+                5121 => data[0] as u32,
+                5123 => u16::from_le_bytes([data[0], data[1]]) as u32,
+                _ => u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+            }
+        })?;
+
+        Ok(values)
+    }
+
+    fn read_accessor<T, F>(&self, index: usize, read: F) -> Result<(Vec<T>, usize), ModelError>
+        where T: Default + Clone, F: Fn(&[u8], u64) -> T
+    {
+        let accessor = self.element("accessors", index)?;
+
+        if accessor.get("sparse").is_some() {
+            return Err(invalid(self.path, format!("accessors[{}] is sparse, which isn't supported", index)));
+        }
+
+        let count = get_usize(accessor, "count").unwrap_or(0);
+        let component_type = accessor.get("componentType").and_then(Value::as_u64).unwrap_or(0);
+        let component_size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(invalid(self.path, format!(
+                "accessors[{}] has unknown component type {}", index, component_type
+            )))
+        };
+        let components = match accessor.get("type").and_then(Value::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            other => return Err(invalid(self.path, format!("accessors[{}] has unknown type {:?}", index, other)))
+        };
+
+        let runs_off = || invalid(self.path, format!("accessors[{}] runs off the end of its buffer view", index));
+        let element_size = component_size * components;
+        let value_count = count.checked_mul(components).ok_or_else(runs_off)?;
+
+        // An accessor with no buffer view is all zeros.
+        let view_index = match get_usize(accessor, "bufferView") {
+            Some(view_index) => view_index,
+            None => {
+                if count.checked_mul(element_size).is_none_or(|size| size > self.data_size) {
+                    return Err(invalid(self.path, format!(
+                        "accessors[{}] has {} elements, more than the file could hold", index, count
+                    )));
+                }
+                return Ok((vec![T::default(); value_count], components));
+            }
+        };
+
+        let (data, stride) = self.buffer_view(view_index)?;
+        let offset = get_usize(accessor, "byteOffset").unwrap_or(0);
+        let stride = stride.unwrap_or(element_size);
+
+        if count > 0 {
+            let end = stride.checked_mul(count - 1)
+                .and_then(|size| size.checked_add(offset))
+                .and_then(|size| size.checked_add(element_size))
+                .ok_or_else(runs_off)?;
+            if end > data.len() {
+                return Err(runs_off());
+            }
+        }
+
+        let mut values = Vec::with_capacity(value_count);
+        for element in 0..count {
+            for component in 0..components {
+                let start = offset + element * stride + component * component_size;
+                values.push(read(&data[start..start + component_size], component_type));
+            }
+        }
+
+        Ok((values, components))
+    }
+
+    fn material(&self, index: usize) -> Result<Material, ModelError> {
+        let json = self.element("materials", index)?;
+        let mut material = default_material();
+        material.name = match json.get("name").and_then(Value::as_str) {
+            Some(name) => String::from(name),
+            None => format!("material{}", index)
+        };
+
+        if let Some(pbr) = json.get("pbrMetallicRoughness") {
+            let base_color = get_floats(pbr, "baseColorFactor", [1.0, 1.0, 1.0, 1.0]);
+            material.diffuse_color = base_color[0..3].to_vec();
+            material.dissolve = base_color[3];
+            material.metallic = get_f32(pbr, "metallicFactor").unwrap_or(1.0);
+            material.roughness = get_f32(pbr, "roughnessFactor").unwrap_or(1.0);
+            material.diffuse_map = self.texture_map(pbr.get("baseColorTexture"))?;
+            material.metallic_roughness_map = self.texture_map(pbr.get("metallicRoughnessTexture"))?;
+        }
+
+        if json.get("alphaMode").and_then(Value::as_str).unwrap_or("OPAQUE") == "OPAQUE" {
+            material.dissolve = 1.0;
+        }
+
+        material.emissive_color = get_floats(json, "emissiveFactor", [0.0, 0.0, 0.0]).to_vec();
+        material.emissive_map = self.texture_map(json.get("emissiveTexture"))?;
+        material.occlusion_map = self.texture_map(json.get("occlusionTexture"))?;
+        material.bump_map = self.texture_map(json.get("normalTexture"))?;
+        if let (Some(map), Some(normal)) = (material.bump_map.as_mut(), json.get("normalTexture")) {
+            map.bump_multiplier = get_f32(normal, "scale").unwrap_or(1.0);
+        }
+
+        approximate_phong(&mut material);
+
+        Ok(material)
+    }
+
+    /// Turns a texture info object (like `baseColorTexture`) into a map.
+    fn texture_map(&self, info: Option<&Value>) -> Result<Option<TextureMap>, ModelError> {
+        let texture_index = match info.and_then(|info| get_usize(info, "index")) {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+
+        let texture = self.element("textures", texture_index)?;
+        let image_index = match get_usize(texture, "source") {
+            Some(index) => index,
+            None => return Ok(None)
+        };
+        let image = self.element("images", image_index)?;
+
+        let mut map = match image.get("uri").and_then(Value::as_str) {
+            Some(uri) if !uri.starts_with("data:") => TextureMap::new(self.resolve(uri)),
+            Some(uri) => {
+                let mut map = TextureMap::new(self.embedded_name(image_index));
+                map.data = Some(self.load_uri(uri)?);
+                map
+            }
+            None => match get_usize(image, "bufferView") {
+                Some(view_index) => {
+                    let mut map = TextureMap::new(self.embedded_name(image_index));
+                    map.data = Some(self.buffer_view(view_index)?.0.to_vec());
+                    map
+                }
+                None => return Err(invalid(self.path, format!("images[{}] has no data", image_index)))
+            }
+        };
+
+        if let Some(sampler) = get_usize(texture, "sampler") {
+            let sampler = self.element("samplers", sampler)?;
+            let wrap_s = sampler.get("wrapS").and_then(Value::as_u64);
+            let wrap_t = sampler.get("wrapT").and_then(Value::as_u64);
+            map.clamp = wrap_s == Some(CLAMP_TO_EDGE) && wrap_t == Some(CLAMP_TO_EDGE);
+        }
+
+        Ok(Some(map))
+    }
+
+    /// A made-up path that's unique to this image, so embedded images can be told apart.
+    fn embedded_name(&self, image_index: usize) -> PathBuf {
+        PathBuf::from(format!("{}#images[{}]", self.path.display(), image_index))
+    }
+
+    /// The nodes of the default scene, or if there aren't any scenes, every node that isn't
+    /// something else's child.
+    fn root_nodes(&self) -> Result<Vec<usize>, ModelError> {
+        let scene_index = get_usize(self.json, "scene").unwrap_or(0);
+
+        if let Some(scene) = self.array("scenes").get(scene_index) {
+            return Ok(get_indices(scene, "nodes"));
+        }
+
+        let nodes = self.array("nodes");
+        let mut is_child = vec![false; nodes.len()];
+        for node in nodes {
+            for child in get_indices(node, "children") {
+                if child < is_child.len() {
+                    is_child[child] = true;
+                }
+            }
+        }
+
+        Ok((0..nodes.len()).filter(|&index| !is_child[index]).collect())
+    }
+
+    /// Adds a node and everything under it. The nodes have to form a tree, so reaching one that's
+    /// in `visited` already means it has two parents or is its own ancestor.
+    fn visit_node(&self, index: usize, parent: Matrix, visited: &mut [bool], builder: &mut Builder) -> Result<(), ModelError> {
+        let node = self.element("nodes", index)?;
+        if visited[index] {
+            return Err(invalid(self.path, format!("nodes[{}] is reached more than once, but nodes have to form a tree", index)));
+        }
+        visited[index] = true;
+        let transform = multiply(&parent, &local_transform(node));
+
+        if let Some(mesh_index) = get_usize(node, "mesh") {
+            let mesh = self.element("meshes", mesh_index)?;
+            let name = node.get("name").or_else(|| mesh.get("name")).and_then(Value::as_str)
+                .map(String::from)
+                .unwrap_or_else(|| format!("nodes[{}]", index));
+
+            let start = builder.indices.len();
+            let first_submesh = builder.submeshes.len();
+
+            let primitives = mesh.get("primitives").and_then(Value::as_array).map(Vec::as_slice).unwrap_or(&[]);
+            for primitive in primitives {
+                self.add_primitive(primitive, &transform, builder)?;
+            }
+
+            if builder.indices.len() > start {
+                builder.objects.push(Object {
+                    name,
+                    start,
+                    count: builder.indices.len() - start,
+                    submeshes: first_submesh..builder.submeshes.len(),
+                    groups: vec![]
+                });
+            }
+        }
+
+        for child in get_indices(node, "children") {
+            self.visit_node(child, transform, visited, builder)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_primitive(&self, primitive: &Value, transform: &Matrix, builder: &mut Builder) -> Result<(), ModelError> {
+        let mode = primitive.get("mode").and_then(Value::as_u64).unwrap_or(MODE_TRIANGLES);
+        if mode != MODE_TRIANGLES && mode != MODE_TRIANGLE_STRIP && mode != MODE_TRIANGLE_FAN {
+            log!("Skipping a primitive with mode {}, only triangles are supported.", mode);
+            return Ok(());
+        }
+
+        let attributes = primitive.get("attributes");
+        let attribute = |name: &str| attributes.and_then(|attributes| get_usize(attributes, name));
+
+        let (positions, position_components) = match attribute("POSITION") {
+            Some(accessor) => self.read_floats(accessor)?,
+            None => {
+                log!("Skipping a primitive without positions.");
+                return Ok(());
+            }
+        };
+        if position_components != 3 {
+            return Err(invalid(self.path, format!("POSITION has {} components, not 3", position_components)));
+        }
+        let vertex_count = positions.len() / 3;
+
+        // Every attribute needs one value per vertex, of the right size, or the vertices would
+        // read past the end of it.
+        let normals = match attribute("NORMAL") {
+            Some(accessor) => Some(self.read_floats(accessor)?.0),
+            None => None
+        };
+        if normals.as_ref().is_some_and(|normals| normals.len() != vertex_count * 3) {
+            return Err(invalid(self.path, format!("NORMAL doesn't have one 3D normal for each of the {} vertices", vertex_count)));
+        }
+
+        let texture_coordinates = match attribute("TEXCOORD_0") {
+            Some(accessor) => Some(self.read_floats(accessor)?.0),
+            None => None
+        };
+        if texture_coordinates.as_ref().is_some_and(|coordinates| coordinates.len() != vertex_count * 2) {
+            return Err(invalid(self.path, format!(
+                "TEXCOORD_0 doesn't have one 2D texture coordinate for each of the {} vertices", vertex_count
+            )));
+        }
+
+        let indices = match get_usize(primitive, "indices") {
+            Some(accessor) => self.read_indices(accessor)?,
+            None => (0..vertex_count as u32).collect()
+        };
+        if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertex_count) {
+            return Err(invalid(self.path, format!("index {} is out of bounds (there are {} vertices)", index, vertex_count)));
+        }
+
+        let mut triangles: Vec<[u32; 3]> = vec![];
+        if mode == MODE_TRIANGLES {
+            for triangle in indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+                triangles.push([triangle[0], triangle[1], triangle[2]]);
+            }
+        } else if mode == MODE_TRIANGLE_STRIP {
+            for i in 0..indices.len().saturating_sub(2) {
+                // Every other triangle in a strip is wound backwards.
+                if i % 2 == 0 {
+                    triangles.push([indices[i], indices[i + 1], indices[i + 2]]);
+                } else {
+                    triangles.push([indices[i + 1], indices[i], indices[i + 2]]);
+                }
+            }
+        } else {
+            for i in 1..indices.len().saturating_sub(1) {
+                triangles.push([indices[0], indices[i], indices[i + 1]]);
+            }
+        }
+
+        // Mirroring transforms turn the triangles inside out, so flip them back.
+        let normal_matrix = normal_matrix(transform);
+        if determinant(transform) < 0.0 {
+            for triangle in triangles.iter_mut() {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let vertex = |index: usize, normal: [f32; 3]| {
+            let position = transform_point(transform, [positions[index * 3], positions[index * 3 + 1], positions[index * 3 + 2]]);
+            let texture_coordinate = match &texture_coordinates {
+                Some(coordinates) => [coordinates[index * 2], coordinates[index * 2 + 1]],
+                None => [0.0, 0.0]
+            };
+
+            [
+                position[0], position[1], position[2],
+                normal[0], normal[1], normal[2],
+                texture_coordinate[0], texture_coordinate[1]
+            ]
+        };
+
+        if normals.is_none() {
+            builder.missing_normals += triangles.len() * 3;
+        }
+        if texture_coordinates.is_none() {
+            builder.missing_texture_coordinates += triangles.len() * 3;
+        }
+
+        let start = builder.indices.len();
+
+        match &normals {
+            Some(normals) => {
+                let base = (builder.vertices.len() / builder.layout.stride) as u32;
+
+                for index in 0..vertex_count {
+                    let normal = transform_normal(&normal_matrix, [normals[index * 3], normals[index * 3 + 1], normals[index * 3 + 2]]);
+                    builder.vertices.extend_from_slice(&vertex(index, normal));
+                }
+
+                for triangle in &triangles {
+                    builder.indices.extend(triangle.iter().map(|&index| base + index));
+                }
+            }
+            None => {
+                // The spec says missing normals mean flat shading, so every triangle needs its
+                // own copies of its vertices.
+                for triangle in &triangles {
+                    let corners: Vec<[f32; 8]> = triangle.iter().map(|&index| vertex(index as usize, [0.0; 3])).collect();
+                    let normal = super::normalize(super::cross(
+                        [corners[1][0] - corners[0][0], corners[1][1] - corners[0][1], corners[1][2] - corners[0][2]],
+                        [corners[2][0] - corners[0][0], corners[2][1] - corners[0][1], corners[2][2] - corners[0][2]]
+                    ));
+
+                    for mut corner in corners {
+                        corner[3..6].copy_from_slice(&normal);
+                        builder.indices.push((builder.vertices.len() / builder.layout.stride) as u32);
+                        builder.vertices.extend_from_slice(&corner);
+                    }
+                }
+            }
+        }
+
+        let material_index = match get_usize(primitive, "material") {
+            Some(index) if index < builder.material_count => index,
+            Some(index) => return Err(invalid(self.path, format!("materials[{}] doesn't exist", index))),
+            None => *builder.default_material.get_or_insert(builder.material_count)
+        };
+
+        if builder.indices.len() > start {
+            builder.submeshes.push(Submesh {
+                material_index,
+                start,
+                count: builder.indices.len() - start,
+                bounds: Bounds::empty(),
+                bounding_sphere: BoundingSphere::empty()
+            });
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Splits a GLB into its JSON chunk and (if there is one) its binary chunk.
+fn split_glb<'a>(path: &Path, bytes: &'a [u8]) -> Result<(&'a [u8], Option<&'a [u8]>), ModelError> {
+    if bytes.len() < 12 {
+        return Err(invalid(path, String::from("GLB header is cut off")));
+    }
+
+    let version = read_u32(bytes, 4);
+    if version != 2 {
+        return Err(invalid(path, format!("only version 2 GLB files are supported, this is version {}", version)));
+    }
+
+    let length = (read_u32(bytes, 8) as usize).min(bytes.len());
+    let mut offset = 12;
+    let mut json = None;
+    let mut binary = None;
+
+    while offset + 8 <= length {
+        let chunk_length = read_u32(bytes, offset) as usize;
+        let chunk_type = read_u32(bytes, offset + 4);
+        let chunk = match bytes.get(offset + 8..offset + 8 + chunk_length) {
+            Some(chunk) => chunk,
+            None => return Err(invalid(path, String::from("GLB chunk runs off the end of the file")))
+        };
+
+        if chunk_type == GLB_JSON_CHUNK && json.is_none() {
+            json = Some(chunk);
+        } else if chunk_type == GLB_BINARY_CHUNK && binary.is_none() {
+            binary = Some(chunk);
+        }
+
+        // Chunks are padded to four bytes.
+        offset += 8 + chunk_length.div_ceil(4) * 4;
+    }
+
+    match json {
+        Some(json) => Ok((json, binary)),
+        None => Err(invalid(path, String::from("GLB has no JSON chunk")))
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_component(data: &[u8], component_type: u64) -> f32 {
+    match component_type {
+        5120 => data[0] as i8 as f32,
+        5121 => data[0] as f32,
+        5122 => i16::from_le_bytes([data[0], data[1]]) as f32,
+        5123 => u16::from_le_bytes([data[0], data[1]]) as f32,
+        5125 => u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as f32,
+        _ => f32::from_le_bytes([data[0], data[1], data[2], data[3]])
+    }
+}
+
+fn invalid(path: &Path, reason: String) -> ModelError {
+    ModelError::Malformed { path: path.to_path_buf(), reason }
+}
+
+fn get_usize(value: &Value, key: &str) -> Option<usize> {
+    value.get(key).and_then(Value::as_u64).map(|number| number as usize)
+}
+
+fn get_f32(value: &Value, key: &str) -> Option<f32> {
+    value.get(key).and_then(Value::as_f64).map(|number| number as f32)
+}
+
+fn get_indices(value: &Value, key: &str) -> Vec<usize> {
+    match value.get(key).and_then(Value::as_array) {
+        Some(array) => array.iter().filter_map(Value::as_u64).map(|index| index as usize).collect(),
+        None => vec![]
+    }
+}
+
+/// Reads a fixed-size array of numbers, falling back to `defaults` if it's missing or the wrong
+/// size.
+fn get_floats<A: AsMut<[f32]>>(value: &Value, key: &str, mut defaults: A) -> A {
+    if let Some(array) = value.get(key).and_then(Value::as_array) {
+        if array.len() == defaults.as_mut().len() {
+            for (slot, number) in defaults.as_mut().iter_mut().zip(array) {
+                *slot = number.as_f64().unwrap_or(0.0) as f32;
+            }
+        }
+    }
+
+    defaults
+}
+
+/// The material glTF uses for primitives that don't name one.
+fn default_material() -> Material {
+    let mut material = Material::new();
+    material.name = String::from("default");
+    material.ambient_percentage = vec![1.0, 1.0, 1.0];
+    material.diffuse_color = vec![1.0, 1.0, 1.0];
+    material.emissive_color = vec![0.0, 0.0, 0.0];
+    material.metallic = 1.0;
+    material.roughness = 1.0;
+    approximate_phong(&mut material);
+
+    material
+}
+
+/// Fills in the Phong terms the renderer uses from the metallic-roughness ones. Metals reflect
+/// their own color, everything else reflects about 4% white, and rougher means a wider highlight.
+fn approximate_phong(material: &mut Material) {
+    let metallic = material.metallic.clamp(0.0, 1.0);
+
+    material.specular_color = material.diffuse_color.iter()
+        .map(|&color| 0.04 + (color - 0.04) * metallic)
+        .collect();
+    material.shininess = 2.0 + (1.0 - material.roughness.clamp(0.0, 1.0)).powi(2) * 254.0;
+}
+
+/// The node's own transform, from either `matrix` or translation/rotation/scale.
+fn local_transform(node: &Value) -> Matrix {
+    if node.get("matrix").is_some() {
+        return get_floats(node, "matrix", IDENTITY);
+    }
+
+    let [tx, ty, tz] = get_floats(node, "translation", [0.0, 0.0, 0.0]);
+    let [x, y, z, w] = get_floats(node, "rotation", [0.0, 0.0, 0.0, 1.0]);
+    let [sx, sy, sz] = get_floats(node, "scale", [1.0, 1.0, 1.0]);
+
+    // Column-major, like glTF and OpenGL.
+    [
+        (1.0 - 2.0 * (y * y + z * z)) * sx, (2.0 * (x * y + z * w)) * sx, (2.0 * (x * z - y * w)) * sx, 0.0,
+        (2.0 * (x * y - z * w)) * sy, (1.0 - 2.0 * (x * x + z * z)) * sy, (2.0 * (y * z + x * w)) * sy, 0.0,
+        (2.0 * (x * z + y * w)) * sz, (2.0 * (y * z - x * w)) * sz, (1.0 - 2.0 * (x * x + y * y)) * sz, 0.0,
+        tx, ty, tz, 1.0
+    ]
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [0.0; 16];
+
+    for column in 0..4 {
+        for row in 0..4 {
+            result[column * 4 + row] = (0..4).map(|k| a[k * 4 + row] * b[column * 4 + k]).sum();
+        }
+    }
+
+    result
+}
+
+fn transform_point(matrix: &Matrix, point: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+
+    for row in 0..3 {
+        result[row] = matrix[row] * point[0] + matrix[4 + row] * point[1] + matrix[8 + row] * point[2] + matrix[12 + row];
+    }
+
+    result
+}
+
+fn column(matrix: &Matrix, index: usize) -> [f32; 3] {
+    [matrix[index * 4], matrix[index * 4 + 1], matrix[index * 4 + 2]]
+}
+
+fn determinant(matrix: &Matrix) -> f32 {
+    let cross = super::cross(column(matrix, 1), column(matrix, 2));
+    let first = column(matrix, 0);
+
+    first[0] * cross[0] + first[1] * cross[1] + first[2] * cross[2]
+}
+
+/// The columns of the cofactor matrix, which points normals the same way as the inverse
+/// transpose but doesn't need a division.
+fn normal_matrix(matrix: &Matrix) -> [[f32; 3]; 3] {
+    let sign = if determinant(matrix) < 0.0 { -1.0 } else { 1.0 };
+    let columns = [column(matrix, 0), column(matrix, 1), column(matrix, 2)];
+    let scale = |vector: [f32; 3]| [vector[0] * sign, vector[1] * sign, vector[2] * sign];
+
+    [
+        scale(super::cross(columns[1], columns[2])),
+        scale(super::cross(columns[2], columns[0])),
+        scale(super::cross(columns[0], columns[1]))
+    ]
+}
+
+fn transform_normal(matrix: &[[f32; 3]; 3], normal: [f32; 3]) -> [f32; 3] {
+    let mut result = [0.0; 3];
+
+    for row in 0..3 {
+        result[row] = matrix[0][row] * normal[0] + matrix[1][row] * normal[1] + matrix[2][row] * normal[2];
+    }
+
+    super::normalize(result)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let mut accumulator: u32 = 0;
+    let mut bits = 0;
+
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None
+        };
+
+        accumulator = (accumulator << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            data.push((accumulator >> bits) as u8);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+
+    Some(data)
+}
+
+/// Undoes URI escaping like `%20`, which exporters use for spaces in file names.
+fn decode_percent(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
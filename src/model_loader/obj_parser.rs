@@ -0,0 +1,256 @@
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str;
+
+use rayon::prelude::*;
+
+use super::{parse_vector, LineType, ModelError, Tokens};
+
+
+/// Chunks smaller than this aren't worth handing to another thread.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// A face point as it's written in the file: the vertex, texture and normal indices, if they're
+/// there. They're one-based, and negative ones count back from the most recent element, so they
+/// can't be checked until we know how much came before the chunk.
+#[derive(Debug, Clone, Copy)]
+pub struct RawPoint {
+    pub indices: [Option<isize>; 3],
+    pub column: usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ElementKind {
+    Face,
+    Line,
+    Point
+}
+
+impl ElementKind {
+    /// How many indices each point can have: faces can have all three, lines can't have normals,
+    /// and points only have a vertex.
+    fn slots(self) -> usize {
+        match self {
+            ElementKind::Face => 3,
+            ElementKind::Line => 2,
+            ElementKind::Point => 1
+        }
+    }
+}
+
+/// The lines of a chunk that depend on what came before them, in file order.
+pub enum Command<'a> {
+    /// A face, polyline or set of points made of `points[points]`. `counts` is how many
+    /// positions, texture coordinates and normals the chunk had read before it.
+    Element { kind: ElementKind, line: usize, points: Range<usize>, counts: [usize; 3] },
+    MaterialLibrary { name: &'a str },
+    UseMaterial { line: usize, column: usize, name: &'a str },
+    Object { name: &'a str },
+    /// A group with the names `names[names]`.
+    Group { names: Range<usize> },
+    SmoothingGroup(u32)
+}
+
+/// Everything read from one chunk of an OBJ file. Strings are borrowed from the file, and the
+/// faces share one list of points, so a chunk only allocates a handful of times.
+pub struct Chunk<'a> {
+    pub positions: Vec<[f32; 3]>,
+    pub texture_coordinates: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 3]>,
+    pub points: Vec<RawPoint>,
+    pub names: Vec<&'a str>,
+    pub commands: Vec<Command<'a>>
+}
+
+/// A piece of the file that starts and ends on a line boundary.
+pub struct Span<'a> {
+    pub bytes: &'a [u8],
+    pub first_line: usize
+}
+
+/// Splits a file into roughly `CHUNK_SIZE` pieces at line breaks, and works out which line each
+/// one starts on.
+pub fn split(bytes: &[u8]) -> Vec<Span<'_>> {
+    let mut pieces: Vec<&[u8]> = vec![];
+    let mut start = 0;
+
+    while start < bytes.len() {
+        let mut end = (start + CHUNK_SIZE).min(bytes.len());
+        while end < bytes.len() && bytes[end - 1] != b'\n' {
+            end += 1;
+        }
+
+        pieces.push(&bytes[start..end]);
+        start = end;
+    }
+
+    let line_counts: Vec<usize> = pieces.par_iter()
+        .map(|piece| piece.iter().filter(|&&byte| byte == b'\n').count())
+        .collect();
+
+    let mut first_line = 1;
+    pieces.into_iter().zip(line_counts).map(|(bytes, line_count)| {
+        let span = Span { bytes, first_line };
+        first_line += line_count;
+        span
+    }).collect()
+}
+
+/// Parses a run of spans in parallel. The results come back in the same order.
+pub fn parse_all<'a>(path: &'a Path, spans: &[Span<'a>]) -> Vec<Result<Chunk<'a>, ModelError>> {
+    spans.par_iter().map(|span| parse(path, span)).collect()
+}
+
+fn parse<'a>(path: &'a Path, span: &Span<'a>) -> Result<Chunk<'a>, ModelError> {
+    let text = str::from_utf8(span.bytes).map_err(|error| ModelError::Io {
+        path: path.to_path_buf(),
+        error: io::Error::new(io::ErrorKind::InvalidData, error)
+    })?;
+
+    let mut chunk = Chunk {
+        positions: vec![],
+        texture_coordinates: vec![],
+        normals: vec![],
+        points: vec![],
+        names: vec![],
+        commands: vec![]
+    };
+
+    for (line_index, line) in text.lines().enumerate() {
+        let line_number = span.first_line + line_index;
+        let mut tokens = Tokens::new(path, line_number, line);
+
+        let line_type = match tokens.next() {
+            Some(token) => match token.text {
+                "v" => LineType::Vertex,
+                "vt" => LineType::Texture,
+                "vn" => LineType::Normal,
+                "f" => LineType::Face,
+                "l" => LineType::Line,
+                "p" => LineType::Point,
+                "#" => LineType::Comment,
+                "mtllib" => LineType::MaterialReference,
+                "usemtl" => LineType::UseMaterial,
+                "s" => LineType::SmoothingGroup,
+                "o" => LineType::Object,
+                "g" => LineType::Group,
+                _ => LineType::Unknown
+            },
+            None => continue
+        };
+
+        match line_type {
+            LineType::Vertex => {
+                let mut position = [0.0; 3];
+                parse_vector(tokens, &mut position)?;
+                chunk.positions.push(position);
+            }
+            LineType::Normal => {
+                let mut normal = [0.0; 3];
+                parse_vector(tokens, &mut normal)?;
+                chunk.normals.push(normal);
+            }
+            LineType::Texture => {
+                let mut texture_coordinate = [0.0; 2];
+                parse_vector(tokens, &mut texture_coordinate)?;
+                chunk.texture_coordinates.push(texture_coordinate);
+            }
+            LineType::MaterialReference => {
+                let name = tokens.next_argument("mtllib")?;
+                chunk.commands.push(Command::MaterialLibrary { name: name.text });
+            }
+            LineType::Face | LineType::Line | LineType::Point => {
+                let (kind, keyword) = match line_type {
+                    LineType::Face => (ElementKind::Face, "f"),
+                    LineType::Line => (ElementKind::Line, "l"),
+                    _ => (ElementKind::Point, "p")
+                };
+                let start = chunk.points.len();
+
+                while let Some(token) = tokens.next() {
+                    let malformed = || ModelError::MalformedFace {
+                        location: tokens.location(token.column),
+                        token: String::from(token.text)
+                    };
+
+                    let mut indices = [None; 3];
+                    for (slot, text) in token.text.split('/').enumerate() {
+                        if slot >= kind.slots() {
+                            return Err(malformed());
+                        }
+                        if text.is_empty() {
+                            continue;
+                        }
+
+                        indices[slot] = match text.parse::<isize>() {
+                            Ok(index) => Some(index),
+                            Err(_) => return Err(malformed())
+                        };
+                    }
+
+                    if indices[0].is_none() {
+                        return Err(malformed());
+                    }
+
+                    chunk.points.push(RawPoint { indices, column: token.column });
+                }
+
+                let found = chunk.points.len() - start;
+                match kind {
+                    ElementKind::Face if found < 3 => {
+                        return Err(ModelError::NotEnoughFacePoints { location: tokens.end_location(), found });
+                    }
+                    ElementKind::Line if found < 2 => {
+                        return Err(ModelError::NotEnoughLinePoints { location: tokens.end_location(), found });
+                    }
+                    ElementKind::Point if found < 1 => {
+                        return Err(ModelError::MissingArgument { location: tokens.end_location(), keyword: String::from(keyword) });
+                    }
+                    _ => ()
+                }
+
+                chunk.commands.push(Command::Element {
+                    kind,
+                    line: line_number,
+                    points: start..chunk.points.len(),
+                    counts: [chunk.positions.len(), chunk.texture_coordinates.len(), chunk.normals.len()]
+                });
+            }
+            LineType::UseMaterial => {
+                let name = tokens.next_argument("usemtl")?;
+                chunk.commands.push(Command::UseMaterial { line: line_number, column: name.column, name: name.text });
+            }
+            LineType::Object => {
+                let name = tokens.next_argument("o")?;
+                chunk.commands.push(Command::Object { name: tokens.remainder(&name) });
+            }
+            LineType::Group => {
+                let start = chunk.names.len();
+                chunk.names.extend(tokens.map(|token| token.text));
+                chunk.commands.push(Command::Group { names: start..chunk.names.len() });
+            }
+            LineType::SmoothingGroup => {
+                // "off", 0 and a bare "s" all mean flat shading.
+                let smoothing_group = match tokens.next() {
+                    None => 0,
+                    Some(group) if group.text == "off" => 0,
+                    Some(group) => match group.text.parse() {
+                        Ok(number) => number,
+                        Err(_) => return Err(ModelError::InvalidNumber {
+                            location: tokens.location(group.column),
+                            token: String::from(group.text)
+                        })
+                    }
+                };
+                chunk.commands.push(Command::SmoothingGroup(smoothing_group));
+            }
+            LineType::Unknown => {
+                log!("Unknown line type: {}", line);
+            }
+            LineType::Comment => ()
+        }
+    }
+
+    Ok(chunk)
+}
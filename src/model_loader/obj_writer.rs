@@ -0,0 +1,236 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::{AttributeKind, Material, Model, ModelError, TextureMap};
+
+
+/// Writes a model out as an OBJ file, with its materials in an MTL file next to it that has the
+/// same name. Positions, normals, texture coordinates, objects, groups, materials, lines and
+/// points are kept. Vertex colors have nowhere to go in OBJ, so they're left out.
+///
+/// Embedded textures are written out as image files next to the MTL file.
+pub fn save_obj(model: &Model, path: &Path) -> Result<(), ModelError> {
+    let material_path = path.with_extension("mtl");
+    let material_names = unique_material_names(&model.materials);
+
+    let material_filename = material_path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let mut obj = String::new();
+    writeln!(obj, "# {} vertices, {} triangles", model.vertices.len() / model.layout.stride, model.indices.len() / 3).unwrap();
+    writeln!(obj, "mtllib {}", material_filename).unwrap();
+
+    let layout = &model.layout;
+    let normal = layout.get(AttributeKind::Normal);
+    let texture_coordinate = layout.get(AttributeKind::TextureCoordinate);
+
+    // Every attribute of a vertex is written out at once, so a vertex's `v`, `vt` and `vn` all
+    // have the same index.
+    for vertex in model.vertices.chunks(layout.stride) {
+        if let Some(position) = layout.get(AttributeKind::Position) {
+            let position = &vertex[position.offset..position.offset + position.size];
+            writeln!(obj, "v {}", join(position)).unwrap();
+        }
+        if let Some(texture_coordinate) = texture_coordinate {
+            let coordinate = &vertex[texture_coordinate.offset..texture_coordinate.offset + texture_coordinate.size];
+            writeln!(obj, "vt {}", join(coordinate)).unwrap();
+        }
+        if let Some(normal) = normal {
+            let normal = &vertex[normal.offset..normal.offset + normal.size];
+            writeln!(obj, "vn {}", join(normal)).unwrap();
+        }
+    }
+
+    let object_starts: HashMap<usize, &str> = model.objects.iter()
+        .filter(|object| object.count > 0)
+        .map(|object| (object.start, object.name.as_str()))
+        .collect();
+    let group_starts: HashMap<usize, &[String]> = model.objects.iter()
+        .flat_map(|object| object.groups.iter())
+        .filter(|group| group.count > 0)
+        .map(|group| (group.start, group.names.as_slice()))
+        .collect();
+    let submesh_starts: HashMap<usize, usize> = model.submeshes.iter()
+        .filter(|submesh| submesh.count > 0)
+        .map(|submesh| (submesh.start, submesh.material_index))
+        .collect();
+
+    for (triangle_index, triangle) in model.indices.chunks(3).enumerate() {
+        let start = triangle_index * 3;
+
+        match object_starts.get(&start) {
+            Some(name) if !name.is_empty() => writeln!(obj, "o {}", name).unwrap(),
+            _ => ()
+        }
+        if let Some(names) = group_starts.get(&start) {
+            writeln!(obj, "g {}", names.join(" ")).unwrap();
+        }
+        if let Some(&material_index) = submesh_starts.get(&start) {
+            match material_names.get(material_index) {
+                Some(name) => writeln!(obj, "usemtl {}", name).unwrap(),
+                None => log!("Submesh at {} uses material {}, which doesn't exist.", start, material_index)
+            }
+        }
+
+        obj.push('f');
+        for &index in triangle {
+            let index = index + 1;
+            match (texture_coordinate.is_some(), normal.is_some()) {
+                (true, true) => write!(obj, " {}/{}/{}", index, index, index).unwrap(),
+                (true, false) => write!(obj, " {}/{}", index, index).unwrap(),
+                (false, true) => write!(obj, " {}//{}", index, index).unwrap(),
+                (false, false) => write!(obj, " {}", index).unwrap()
+            }
+        }
+        obj.push('\n');
+    }
+
+    // Lines can't have normals in OBJ, and points can only have a position.
+    for segment in model.line_indices.chunks(2) {
+        obj.push('l');
+        for &index in segment {
+            let index = index + 1;
+            if texture_coordinate.is_some() {
+                write!(obj, " {}/{}", index, index).unwrap();
+            } else {
+                write!(obj, " {}", index).unwrap();
+            }
+        }
+        obj.push('\n');
+    }
+    for &index in &model.point_indices {
+        writeln!(obj, "p {}", index + 1).unwrap();
+    }
+
+    let mut mtl = String::new();
+    for (material_index, (material, name)) in model.materials.iter().zip(&material_names).enumerate() {
+        write_material(&mut mtl, material, name, material_index, &material_path)?;
+    }
+
+    log!("Writing {:?} and {:?}...", path, material_path);
+    fs::write(path, obj).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+    fs::write(&material_path, mtl).map_err(|error| ModelError::Io { path: material_path.clone(), error })?;
+
+    Ok(())
+}
+
+fn write_material(mtl: &mut String, material: &Material, name: &str, material_index: usize, material_path: &Path) -> Result<(), ModelError> {
+    writeln!(mtl, "newmtl {}", name).unwrap();
+
+    let colors = [
+        ("Ka", &material.ambient_percentage),
+        ("Kd", &material.diffuse_color),
+        ("Ks", &material.specular_color),
+        ("Ke", &material.emissive_color),
+        ("Tf", &material.transmission_filter)
+    ];
+    for (keyword, color) in colors.iter() {
+        if !color.is_empty() {
+            writeln!(mtl, "{} {}", keyword, join(color)).unwrap();
+        }
+    }
+
+    writeln!(mtl, "Ns {}", material.shininess).unwrap();
+    writeln!(mtl, "Ni {}", material.optical_density).unwrap();
+    writeln!(mtl, "d {}", material.dissolve).unwrap();
+    writeln!(mtl, "illum {}", material.illumination_model).unwrap();
+
+    let maps = [
+        ("map_Ka", &material.ambient_map),
+        ("map_Kd", &material.diffuse_map),
+        ("map_Ks", &material.specular_map),
+        ("map_Ke", &material.emissive_map),
+        ("map_Ns", &material.shininess_map),
+        ("map_d", &material.dissolve_map),
+        ("map_Bump", &material.bump_map),
+        ("disp", &material.displacement_map)
+    ];
+    for (keyword, map) in maps.iter() {
+        if let Some(map) = map {
+            let filename = texture_filename(map, material_index, keyword, material_path)?;
+            writeln!(mtl, "{}{} {}", keyword, map_options(map), filename).unwrap();
+        }
+    }
+
+    mtl.push('\n');
+    Ok(())
+}
+
+/// Only the options that aren't already the default, so simple maps stay simple.
+fn map_options(map: &TextureMap) -> String {
+    let mut options = String::new();
+
+    if map.offset != [0.0, 0.0, 0.0] {
+        write!(options, " -o {}", join(&map.offset)).unwrap();
+    }
+    if map.scale != [1.0, 1.0, 1.0] {
+        write!(options, " -s {}", join(&map.scale)).unwrap();
+    }
+    if map.turbulence != [0.0, 0.0, 0.0] {
+        write!(options, " -t {}", join(&map.turbulence)).unwrap();
+    }
+    if map.bump_multiplier != 1.0 {
+        write!(options, " -bm {}", map.bump_multiplier).unwrap();
+    }
+    if map.clamp {
+        options.push_str(" -clamp on");
+    }
+    if !map.blend_u {
+        options.push_str(" -blendu off");
+    }
+    if !map.blend_v {
+        options.push_str(" -blendv off");
+    }
+
+    options
+}
+
+/// The name to put in the MTL file for a map. Files next to the MTL file (or under it) are written
+/// relative to it. Embedded images are saved out to their own files first.
+fn texture_filename(map: &TextureMap, material_index: usize, keyword: &str, material_path: &Path) -> Result<String, ModelError> {
+    let directory = material_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let path = match &map.data {
+        Some(data) => {
+            let stem = material_path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+            let image_path = directory.join(format!("{}.{}.{}.{}", stem, material_index, keyword, image_extension(data)));
+
+            fs::write(&image_path, data).map_err(|error| ModelError::Io { path: image_path.clone(), error })?;
+            image_path
+        }
+        None => map.path.clone()
+    };
+
+    let relative: PathBuf = path.strip_prefix(directory).map(Path::to_path_buf).unwrap_or(path);
+    Ok(relative.to_string_lossy().into_owned())
+}
+
+/// Guesses what kind of image some encoded bytes are from the first few of them.
+fn image_extension(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "png"
+    } else if data.starts_with(&[0xff, 0xd8]) {
+        "jpg"
+    } else {
+        "bin"
+    }
+}
+
+/// `newmtl` and `usemtl` only take one word, and every material needs its own name.
+fn unique_material_names(materials: &[Material]) -> Vec<String> {
+    let mut used = HashSet::new();
+
+    materials.iter().enumerate().map(|(index, material)| {
+        let mut name: String = material.name.split_whitespace().collect::<Vec<_>>().join("_");
+        if name.is_empty() || used.contains(&name) {
+            name = format!("{}{}", if name.is_empty() { "material" } else { &name }, index);
+        }
+
+        used.insert(name.clone());
+        name
+    }).collect()
+}
+
+fn join(numbers: &[f32]) -> String {
+    numbers.iter().map(|number| number.to_string()).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,412 @@
+use std::fs;
+use std::path::Path;
+
+use super::{Model, ModelError, TriangleMesh};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8
+        }
+    }
+
+    /// What a color channel of this type counts as full brightness.
+    fn color_scale(&self) -> f64 {
+        match self {
+            ScalarType::Int8 => 127.0,
+            ScalarType::UInt8 => 255.0,
+            ScalarType::Int16 => 32767.0,
+            ScalarType::UInt16 => 65535.0,
+            ScalarType::Int32 => 2147483647.0,
+            ScalarType::UInt32 => 4294967295.0,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0
+        }
+    }
+}
+
+#[derive(Debug)]
+enum PropertyType {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    kind: PropertyType
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>
+}
+
+
+/// Loads a PLY file, in ASCII or either binary byte order. Vertices can have normals, texture
+/// coordinates and colors. Anything other than vertices and faces is skipped.
+pub fn load_ply(path: &Path) -> Result<Model, ModelError> {
+    log!("Reading file {:?}...", path);
+    let bytes = fs::read(path).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    parse(&bytes, path).map_err(|reason| ModelError::Malformed { path: path.to_path_buf(), reason })
+}
+
+fn parse(bytes: &[u8], path: &Path) -> Result<Model, String> {
+    let (format, elements, body_start) = parse_header(bytes)?;
+    log!("PLY format is {:?}, elements are {:?}", format, elements);
+
+    let mut body = match format {
+        Format::Ascii => {
+            let text = String::from_utf8_lossy(&bytes[body_start..]);
+            let values: Vec<String> = text.split_whitespace().map(String::from).collect();
+            Body::Ascii { values, next: 0 }
+        }
+        _ => Body::Binary {
+            data: &bytes[body_start..],
+            offset: 0,
+            big_endian: format == Format::BinaryBigEndian
+        }
+    };
+
+    let mut mesh = TriangleMesh {
+        positions: vec![],
+        normals: None,
+        texture_coordinates: None,
+        colors: None,
+        triangles: vec![],
+        objects: vec![]
+    };
+    let mut polygons: Vec<Vec<u32>> = vec![];
+
+    for element in &elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => polygons = read_faces(&mut body, element)?,
+            _ => {
+                log!("Skipping {} {} elements.", element.count, element.name);
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        read_property(&mut body, property)?;
+                    }
+                }
+            }
+        }
+    }
+
+    for polygon in polygons {
+        if polygon.len() < 3 {
+            return Err(format!("a face needs at least 3 points, found {}", polygon.len()));
+        }
+        if let Some(&index) = polygon.iter().find(|&&index| index as usize >= mesh.positions.len()) {
+            return Err(format!("vertex index {} is out of bounds (there are {})", index, mesh.positions.len()));
+        }
+
+        let positions: Vec<[f32; 3]> = polygon.iter().map(|&index| mesh.positions[index as usize]).collect();
+        for triangle in super::triangulate(&positions) {
+            mesh.triangles.push([polygon[triangle[0]], polygon[triangle[1]], polygon[triangle[2]]]);
+        }
+    }
+
+    mesh.objects.push((String::new(), 0..mesh.triangles.len()));
+
+    log!("{} vertices and {} triangles.", mesh.positions.len(), mesh.triangles.len());
+
+    Ok(mesh.into_model(path))
+}
+
+/// Returns the format, the elements in order, and where the body starts.
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+    let marker = b"end_header";
+    let end = match bytes.windows(marker.len()).position(|window| window == marker) {
+        Some(end) => end,
+        None => return Err(String::from("the header never ends"))
+    };
+
+    // The body starts right after the newline that ends the header.
+    let body_start = match bytes[end..].iter().position(|&byte| byte == b'\n') {
+        Some(newline) => end + newline + 1,
+        None => bytes.len()
+    };
+
+    let header = String::from_utf8_lossy(&bytes[..end]);
+    let mut lines = header.lines();
+
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(String::from("this isn't a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+
+    for (line_index, line) in lines.enumerate() {
+        let line_number = line_index + 2;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.first() {
+            Some(&"format") => {
+                format = match tokens.get(1) {
+                    Some(&"ascii") => Some(Format::Ascii),
+                    Some(&"binary_little_endian") => Some(Format::BinaryLittleEndian),
+                    Some(&"binary_big_endian") => Some(Format::BinaryBigEndian),
+                    other => return Err(format!("line {}: unknown format {:?}", line_number, other))
+                };
+            }
+            Some(&"element") => {
+                let count = tokens.get(2).and_then(|count| count.parse().ok());
+
+                match (tokens.get(1), count) {
+                    (Some(name), Some(count)) => elements.push(Element {
+                        name: String::from(*name),
+                        count,
+                        properties: vec![]
+                    }),
+                    _ => return Err(format!("line {}: malformed element", line_number))
+                }
+            }
+            Some(&"property") => {
+                let element = match elements.last_mut() {
+                    Some(element) => element,
+                    None => return Err(format!("line {}: property outside of an element", line_number))
+                };
+
+                let scalar = |index: usize| tokens.get(index).and_then(|name| ScalarType::from_name(name));
+
+                let property = if tokens.get(1) == Some(&"list") {
+                    match (scalar(2), scalar(3), tokens.get(4)) {
+                        (Some(count), Some(item), Some(name)) => Property {
+                            name: String::from(*name),
+                            kind: PropertyType::List { count, item }
+                        },
+                        _ => return Err(format!("line {}: malformed list property", line_number))
+                    }
+                } else {
+                    match (scalar(1), tokens.get(2)) {
+                        (Some(kind), Some(name)) => Property {
+                            name: String::from(*name),
+                            kind: PropertyType::Scalar(kind)
+                        },
+                        _ => return Err(format!("line {}: malformed property", line_number))
+                    }
+                };
+
+                element.properties.push(property);
+            }
+            Some(&"comment") | Some(&"obj_info") | None => (),
+            Some(other) => log!("Unknown PLY header line: {}", other)
+        }
+    }
+
+    match format {
+        Some(format) => Ok((format, elements, body_start)),
+        None => Err(String::from("the header doesn't say what format the file is"))
+    }
+}
+
+fn read_vertices(body: &mut Body, element: &Element, mesh: &mut TriangleMesh) -> Result<(), String> {
+    let find = |names: &[&str]| element.properties.iter().position(|property| names.contains(&property.name.as_str()));
+
+    let position_slots = [find(&["x"]), find(&["y"]), find(&["z"])];
+    if position_slots.iter().any(Option::is_none) {
+        return Err(String::from("the vertex element is missing an x, y or z property"));
+    }
+    let normal_slots = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let texture_slots = [
+        find(&["s", "u", "texture_s", "texture_u"]),
+        find(&["t", "v", "texture_t", "texture_v"])
+    ];
+    let color_slots = [
+        find(&["red", "diffuse_red", "r"]),
+        find(&["green", "diffuse_green", "g"]),
+        find(&["blue", "diffuse_blue", "b"]),
+        find(&["alpha", "diffuse_alpha", "a"])
+    ];
+
+    let has_normals = normal_slots.iter().all(Option::is_some);
+    let has_texture_coordinates = texture_slots.iter().all(Option::is_some);
+    let has_colors = color_slots[0..3].iter().all(Option::is_some);
+
+    let mut normals = vec![];
+    let mut texture_coordinates = vec![];
+    let mut colors = vec![];
+
+    for _ in 0..element.count {
+        let mut values = vec![0.0; element.properties.len()];
+        for (slot, property) in element.properties.iter().enumerate() {
+            values[slot] = read_property(body, property)?.unwrap_or(0.0);
+        }
+
+        let value = |slot: Option<usize>| slot.map_or(0.0, |slot| values[slot]);
+
+        mesh.positions.push([
+            value(position_slots[0]) as f32,
+            value(position_slots[1]) as f32,
+            value(position_slots[2]) as f32
+        ]);
+
+        if has_normals {
+            normals.push([
+                value(normal_slots[0]) as f32,
+                value(normal_slots[1]) as f32,
+                value(normal_slots[2]) as f32
+            ]);
+        }
+
+        if has_texture_coordinates {
+            texture_coordinates.push([value(texture_slots[0]) as f32, value(texture_slots[1]) as f32]);
+        }
+
+        if has_colors {
+            let channel = |slot: Option<usize>| match slot {
+                Some(slot) => match element.properties[slot].kind {
+                    PropertyType::Scalar(kind) => (values[slot] / kind.color_scale()) as f32,
+                    PropertyType::List { .. } => 0.0
+                },
+                None => 1.0
+            };
+
+            colors.push([channel(color_slots[0]), channel(color_slots[1]), channel(color_slots[2]), channel(color_slots[3])]);
+        }
+    }
+
+    if has_normals {
+        mesh.normals = Some(normals);
+    }
+    if has_texture_coordinates {
+        mesh.texture_coordinates = Some(texture_coordinates);
+    }
+    if has_colors {
+        mesh.colors = Some(colors);
+    }
+
+    Ok(())
+}
+
+fn read_faces(body: &mut Body, element: &Element) -> Result<Vec<Vec<u32>>, String> {
+    // Not allocated up front, since the count comes from the header and a broken one could ask for
+    // anything. The body runs out long before a wrong count gets far.
+    let mut polygons = vec![];
+
+    for face in 0..element.count {
+        let mut polygon = vec![];
+
+        for property in &element.properties {
+            match &property.kind {
+                PropertyType::List { count, item } if property.name == "vertex_indices" || property.name == "vertex_index" => {
+                    let count = body.read(*count)?;
+                    for _ in 0..count as usize {
+                        let index = body.read(*item)?;
+                        if index < 0.0 || index.fract() != 0.0 || index > u32::MAX as f64 {
+                            return Err(format!("face {} has vertex index {}, which isn't a whole number of at least 0", face, index));
+                        }
+                        polygon.push(index as u32);
+                    }
+                }
+                _ => { read_property(body, property)?; }
+            }
+        }
+
+        polygons.push(polygon);
+    }
+
+    Ok(polygons)
+}
+
+/// Reads one property. Scalars return their value, lists are read and thrown away.
+fn read_property(body: &mut Body, property: &Property) -> Result<Option<f64>, String> {
+    match property.kind {
+        PropertyType::Scalar(kind) => Ok(Some(body.read(kind)?)),
+        PropertyType::List { count, item } => {
+            let count = body.read(count)?;
+            for _ in 0..count as usize {
+                body.read(item)?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+
+enum Body<'a> {
+    Ascii { values: Vec<String>, next: usize },
+    Binary { data: &'a [u8], offset: usize, big_endian: bool }
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, kind: ScalarType) -> Result<f64, String> {
+        match self {
+            Body::Ascii { values, next } => {
+                let value = match values.get(*next) {
+                    Some(value) => value,
+                    None => return Err(String::from("the file ends before all the elements were read"))
+                };
+                *next += 1;
+
+                value.parse().map_err(|_| format!("couldn't parse \"{}\" as a number", value))
+            }
+            Body::Binary { data, offset, big_endian } => {
+                let size = kind.size();
+                let bytes = match data.get(*offset..*offset + size) {
+                    Some(bytes) => bytes,
+                    None => return Err(String::from("the file ends before all the elements were read"))
+                };
+                *offset += size;
+
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+
+                Ok(match kind {
+                    ScalarType::Int8 => buffer[0] as i8 as f64,
+                    ScalarType::UInt8 => buffer[0] as f64,
+                    ScalarType::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    ScalarType::UInt16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+                    ScalarType::Int32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    ScalarType::UInt32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    ScalarType::Float32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+                    ScalarType::Float64 => f64::from_le_bytes(buffer)
+                })
+            }
+        }
+    }
+}
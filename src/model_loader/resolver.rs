@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+
+
+/// Finds the files a model refers to, like the MTL files an OBJ file uses and the textures in
+/// them. Models on disk look next to the file that refers to them, but anything else can supply
+/// its own, to load from archives or from memory.
+pub trait Resolver {
+    /// Where the file called `name` lives, when it's referred to by the file at `from`.
+    fn resolve(&self, name: &str, from: &Path) -> PathBuf {
+        match from.parent() {
+            Some(directory) => directory.join(name),
+            None => PathBuf::from(name)
+        }
+    }
+
+    /// Opens a file that `resolve` pointed at.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>>;
+
+    /// The encoded image for a texture map. Returning `None` leaves the image for whoever draws
+    /// the model to load from `TextureMap::path`, which is fine for files on disk but not much
+    /// else, so by default the whole file is read with `open`.
+    fn load_texture(&self, path: &Path) -> Option<io::Result<Vec<u8>>> {
+        Some(self.open(path).and_then(|mut reader| {
+            let mut data = vec![];
+            reader.read_to_end(&mut data)?;
+            Ok(data)
+        }))
+    }
+}
+
+/// Reads files straight off the disk.
+pub struct FileResolver;
+
+impl Resolver for FileResolver {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>> {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+
+    fn load_texture(&self, _path: &Path) -> Option<io::Result<Vec<u8>>> {
+        None
+    }
+}
+
+/// Files kept in memory, by path. Good for models that are built at runtime or baked into the
+/// program with `include_bytes!`.
+#[derive(Default)]
+pub struct MemoryResolver {
+    pub files: HashMap<PathBuf, Vec<u8>>
+}
+
+impl MemoryResolver {
+    pub fn new() -> MemoryResolver {
+        MemoryResolver { files: HashMap::new() }
+    }
+
+    pub fn add<P: Into<PathBuf>, D: Into<Vec<u8>>>(&mut self, path: P, data: D) {
+        self.files.insert(path.into(), data.into());
+    }
+}
+
+impl Resolver for MemoryResolver {
+    fn open(&self, path: &Path) -> io::Result<Box<dyn BufRead + '_>> {
+        match self.files.get(path) {
+            Some(data) => Ok(Box::new(Cursor::new(data.as_slice()))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, format!("there's no file called {:?} in memory", path)))
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use super::{AttributeKind, Model, Submesh};
+
+
+/// A simplified version of a model's triangles. It uses the model's vertices, so only the indices
+/// and the submeshes that draw them are different.
+#[derive(Debug, Clone)]
+pub struct Lod {
+    pub indices: Vec<u32>,
+    /// One per submesh of the model that still has triangles left, in the same order.
+    pub submeshes: Vec<Submesh>,
+    /// Roughly how far, in model units, the surface has moved from the original.
+    pub error: f32
+}
+
+impl Model {
+    /// Builds a chain of `levels` LODs, each with about `reduction` times the triangles of the one
+    /// before it. Simplifying stops early once the surface would have to move further than
+    /// `max_error` times the bounding sphere's radius, and LODs that couldn't be made any simpler
+    /// are left off the end.
+    pub fn generate_lods(&self, levels: usize, reduction: f32, max_error: f32) -> Vec<Lod> {
+        let triangle_count = self.indices.len() / 3;
+        let targets: Vec<usize> = (1..=levels)
+            .map(|level| (triangle_count as f32 * reduction.powi(level as i32)) as usize)
+            .collect();
+
+        let max_cost = (max_error * self.bounding_sphere.radius) as f64;
+        let mut simplifier = match Simplifier::new(self) {
+            Some(simplifier) => simplifier,
+            None => return vec![]
+        };
+
+        let mut lods: Vec<Lod> = vec![];
+        for target in targets {
+            simplifier.simplify(target, max_cost * max_cost);
+
+            let lod = simplifier.snapshot();
+            let previous_count = lods.last().map_or(self.indices.len(), |lod| lod.indices.len());
+            if lod.indices.len() >= previous_count {
+                break;
+            }
+
+            log!("LOD {} has {} triangles, error {}.", lods.len() + 1, lod.indices.len() / 3, lod.error);
+            lods.push(lod);
+        }
+
+        lods
+    }
+}
+
+
+/// The error quadric of Garland and Heckbert: the sum of squared distances to a set of planes,
+/// stored as the upper triangle of a symmetric 4x4 matrix.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// The plane through `point` facing `normal`, which should be normalized.
+    fn from_plane(normal: [f64; 3], point: [f64; 3], weight: f64) -> Quadric {
+        let [a, b, c] = normal;
+        let d = -(a * point[0] + b * point[1] + c * point[2]);
+
+        Quadric([
+            a * a, a * b, a * c, a * d,
+            b * b, b * c, b * d,
+            c * c, c * d,
+            d * d
+        ]).scaled(weight)
+    }
+
+    fn scaled(mut self, factor: f64) -> Quadric {
+        for value in self.0.iter_mut() {
+            *value *= factor;
+        }
+        self
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+    }
+
+    fn evaluate(&self, point: [f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = point;
+
+        q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+/// Moving the position `from` onto `to`, as it stood when `versions` were current.
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    versions: (u32, u32)
+}
+
+// The heap is a max-heap, so cheaper collapses count as greater.
+impl Ord for Collapse {
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+
+/// Edge collapses over the model's positions. Vertices that share a position (because their
+/// normals or texture coordinates differ) move together, and collapses only ever move a position
+/// onto one that already exists, so the model's vertex buffer can be reused as is.
+struct Simplifier<'a> {
+    model: &'a Model,
+    /// The welded position of every vertex.
+    position_of: Vec<usize>,
+    positions: Vec<[f64; 3]>,
+    /// The vertices at each welded position.
+    vertices_at: Vec<Vec<u32>>,
+    /// Which position each one has been collapsed onto. Positions that are still around point at
+    /// themselves.
+    parent: Vec<usize>,
+    quadrics: Vec<Quadric>,
+    /// Bumped whenever a position's surroundings change, so old collapses can be spotted.
+    versions: Vec<u32>,
+
+    /// The model's triangles, as vertex indices.
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    live_count: usize,
+    triangles_at: Vec<Vec<usize>>,
+
+    heap: BinaryHeap<Collapse>,
+    /// The most expensive collapse so far.
+    max_cost: f64
+}
+
+/// Edges on the outline of the mesh or between two materials get extra planes along them with this
+/// much weight, so the outline doesn't get eaten away.
+const BORDER_WEIGHT: f64 = 10.0;
+
+/// A collapse is rejected if it would turn any triangle more than this far. It's the cosine of the
+/// angle between the old and new normals.
+const MIN_NORMAL_DOT: f64 = 0.2;
+
+impl<'a> Simplifier<'a> {
+    fn new(model: &'a Model) -> Option<Simplifier<'a>> {
+        let position = model.layout.get(AttributeKind::Position)?.offset;
+        let stride = model.layout.stride;
+        if stride == 0 {
+            return None;
+        }
+        let vertex_count = model.vertices.len() / stride;
+
+        let mut position_ids: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut position_of = Vec::with_capacity(vertex_count);
+        let mut positions = vec![];
+        let mut vertices_at: Vec<Vec<u32>> = vec![];
+
+        for vertex in 0..vertex_count {
+            let start = vertex * stride + position;
+            let point = [model.vertices[start], model.vertices[start + 1], model.vertices[start + 2]];
+            let key = [point[0].to_bits(), point[1].to_bits(), point[2].to_bits()];
+
+            let id = *position_ids.entry(key).or_insert_with(|| {
+                positions.push([point[0] as f64, point[1] as f64, point[2] as f64]);
+                vertices_at.push(vec![]);
+                positions.len() - 1
+            });
+            position_of.push(id);
+            vertices_at[id].push(vertex as u32);
+        }
+
+        let triangles: Vec<[u32; 3]> = model.indices.chunks(3)
+            .filter(|triangle| triangle.len() == 3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+
+        let mut simplifier = Simplifier {
+            model,
+            parent: (0..positions.len()).collect(),
+            quadrics: vec![Quadric::default(); positions.len()],
+            versions: vec![0; positions.len()],
+            triangles_at: vec![vec![]; positions.len()],
+            position_of,
+            positions,
+            vertices_at,
+            alive: vec![true; triangles.len()],
+            live_count: triangles.len(),
+            triangles,
+            heap: BinaryHeap::new(),
+            max_cost: 0.0
+        };
+
+        simplifier.build_quadrics();
+
+        Some(simplifier)
+    }
+
+    fn build_quadrics(&mut self) {
+        let materials = self.triangle_materials();
+
+        // Each edge, with the first material seen on it and how many triangles use it.
+        let mut edges: HashMap<(usize, usize), (usize, usize, bool)> = HashMap::new();
+
+        for (triangle_index, triangle) in self.triangles.iter().enumerate() {
+            let corners = [
+                self.position_of[triangle[0] as usize],
+                self.position_of[triangle[1] as usize],
+                self.position_of[triangle[2] as usize]
+            ];
+
+            if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                self.alive[triangle_index] = false;
+                self.live_count -= 1;
+                continue;
+            }
+
+            let normal = self.normal(corners);
+            let plane = Quadric::from_plane(normalize(normal), self.positions[corners[0]], 1.0);
+
+            for corner in 0..3 {
+                self.quadrics[corners[corner]].add(&plane);
+                self.triangles_at[corners[corner]].push(triangle_index);
+
+                let (a, b) = (corners[corner], corners[(corner + 1) % 3]);
+                let key = (a.min(b), a.max(b));
+                let edge = edges.entry(key).or_insert((materials[triangle_index], 0, false));
+                edge.1 += 1;
+                if edge.0 != materials[triangle_index] {
+                    edge.2 = true;
+                }
+            }
+        }
+
+        for (triangle_index, triangle) in self.triangles.iter().enumerate() {
+            if !self.alive[triangle_index] {
+                continue;
+            }
+
+            let corners = [
+                self.position_of[triangle[0] as usize],
+                self.position_of[triangle[1] as usize],
+                self.position_of[triangle[2] as usize]
+            ];
+            let normal = normalize(self.normal(corners));
+
+            for corner in 0..3 {
+                let (a, b) = (corners[corner], corners[(corner + 1) % 3]);
+                let (_, count, material_border) = edges[&(a.min(b), a.max(b))];
+                if count != 1 && !material_border {
+                    continue;
+                }
+
+                // A plane through the edge, standing up from the triangle.
+                let direction = subtract(self.positions[b], self.positions[a]);
+                let border_normal = normalize(cross(direction, normal));
+                let border = Quadric::from_plane(border_normal, self.positions[a], BORDER_WEIGHT);
+                self.quadrics[a].add(&border);
+                self.quadrics[b].add(&border);
+            }
+        }
+
+        for &(a, b) in edges.keys() {
+            self.push_collapse(a, b);
+        }
+    }
+
+    /// The material of every triangle, from the submesh it's in.
+    fn triangle_materials(&self) -> Vec<usize> {
+        let mut materials = vec![0; self.triangles.len()];
+        for submesh in &self.model.submeshes {
+            for triangle in submesh.start / 3..(submesh.start + submesh.count) / 3 {
+                if let Some(material) = materials.get_mut(triangle) {
+                    *material = submesh.material_index;
+                }
+            }
+        }
+        materials
+    }
+
+    fn find(&self, mut position: usize) -> usize {
+        while self.parent[position] != position {
+            position = self.parent[position];
+        }
+        position
+    }
+
+    fn corners(&self, triangle: usize) -> [usize; 3] {
+        let triangle = &self.triangles[triangle];
+        [
+            self.find(self.position_of[triangle[0] as usize]),
+            self.find(self.position_of[triangle[1] as usize]),
+            self.find(self.position_of[triangle[2] as usize])
+        ]
+    }
+
+    fn normal(&self, corners: [usize; 3]) -> [f64; 3] {
+        let a = self.positions[corners[0]];
+        cross(subtract(self.positions[corners[1]], a), subtract(self.positions[corners[2]], a))
+    }
+
+    fn push_collapse(&mut self, a: usize, b: usize) {
+        let mut quadric = self.quadrics[a];
+        quadric.add(&self.quadrics[b]);
+
+        let onto_b = quadric.evaluate(self.positions[b]);
+        let onto_a = quadric.evaluate(self.positions[a]);
+        let (from, to, cost) = if onto_b <= onto_a { (a, b, onto_b) } else { (b, a, onto_a) };
+
+        self.heap.push(Collapse {
+            cost: cost.max(0.0),
+            from,
+            to,
+            versions: (self.versions[from], self.versions[to])
+        });
+    }
+
+    /// The positions that share a live triangle with `position`.
+    fn neighbors(&self, position: usize) -> HashSet<usize> {
+        let mut neighbors = HashSet::new();
+        for &triangle in &self.triangles_at[position] {
+            if self.alive[triangle] {
+                neighbors.extend(self.corners(triangle).iter().cloned().filter(|&corner| corner != position));
+            }
+        }
+        neighbors
+    }
+
+    fn can_collapse(&self, from: usize, to: usize) -> bool {
+        let mut shared_triangles = 0;
+
+        for &triangle in &self.triangles_at[from] {
+            if !self.alive[triangle] {
+                continue;
+            }
+
+            let corners = self.corners(triangle);
+            if corners.contains(&to) {
+                shared_triangles += 1;
+                continue;
+            }
+
+            let before = self.normal(corners);
+            if dot(before, before) == 0.0 {
+                continue;
+            }
+
+            let mut moved = corners;
+            for corner in moved.iter_mut() {
+                if *corner == from {
+                    *corner = to;
+                }
+            }
+            let after = self.normal(moved);
+
+            if dot(normalize(before), normalize(after)) < MIN_NORMAL_DOT {
+                return false;
+            }
+        }
+
+        // Two positions with more neighbors in common than triangles in common would pinch the
+        // surface into something that isn't a manifold.
+        let shared_neighbors = self.neighbors(from).intersection(&self.neighbors(to)).count();
+        shared_neighbors <= shared_triangles
+    }
+
+    /// Collapses the cheapest edges until there are at most `target` triangles left or the next
+    /// collapse would cost more than `max_cost`.
+    fn simplify(&mut self, target: usize, max_cost: f64) {
+        while self.live_count > target {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break
+            };
+
+            let (from, to) = (collapse.from, collapse.to);
+            let current = self.parent[from] == from && self.parent[to] == to
+                && collapse.versions == (self.versions[from], self.versions[to]);
+            if !current {
+                continue;
+            }
+
+            if collapse.cost > max_cost {
+                // Keep it around in case a later call allows more error.
+                self.heap.push(collapse);
+                break;
+            }
+
+            if !self.can_collapse(from, to) {
+                continue;
+            }
+
+            self.parent[from] = to;
+            let quadric = self.quadrics[from];
+            self.quadrics[to].add(&quadric);
+            self.versions[to] += 1;
+            self.max_cost = self.max_cost.max(collapse.cost);
+
+            let moved = std::mem::take(&mut self.triangles_at[from]);
+            for triangle in moved {
+                if !self.alive[triangle] {
+                    continue;
+                }
+
+                let corners = self.corners(triangle);
+                if corners[0] == corners[1] || corners[1] == corners[2] || corners[0] == corners[2] {
+                    self.alive[triangle] = false;
+                    self.live_count -= 1;
+                } else {
+                    self.triangles_at[to].push(triangle);
+                }
+            }
+
+            let alive = &self.alive;
+            self.triangles_at[to].retain(|&triangle| alive[triangle]);
+
+            for neighbor in self.neighbors(to) {
+                self.push_collapse(neighbor, to);
+            }
+        }
+    }
+
+    /// The current state as indices into the model's vertices.
+    fn snapshot(&self) -> Lod {
+        let mut remapped: HashMap<u32, u32> = HashMap::new();
+        let mut indices = vec![];
+        let mut submeshes = vec![];
+
+        let mut add_triangles = |start: usize, count: usize, indices: &mut Vec<u32>| {
+            for triangle in start / 3..(start + count) / 3 {
+                if !self.alive.get(triangle).cloned().unwrap_or(false) {
+                    continue;
+                }
+
+                for &vertex in &self.triangles[triangle] {
+                    let index = *remapped.entry(vertex).or_insert_with(|| self.remap_vertex(vertex));
+                    indices.push(index);
+                }
+            }
+        };
+
+        for submesh in &self.model.submeshes {
+            let start = indices.len();
+            add_triangles(submesh.start, submesh.count, &mut indices);
+
+            if indices.len() > start {
+                submeshes.push(Submesh { start, count: indices.len() - start, ..*submesh });
+            }
+        }
+
+        Lod {
+            indices,
+            submeshes,
+            error: self.max_cost.sqrt() as f32
+        }
+    }
+
+    /// Picks the vertex at the position `vertex` was collapsed onto whose normal and texture
+    /// coordinates are closest to its own.
+    fn remap_vertex(&self, vertex: u32) -> u32 {
+        let original = self.position_of[vertex as usize];
+        let position = self.find(original);
+        if position == original {
+            return vertex;
+        }
+
+        let layout = &self.model.layout;
+        let attribute = |vertex: u32, kind: AttributeKind| {
+            layout.get(kind).map(|attribute| {
+                let start = vertex as usize * layout.stride + attribute.offset;
+                &self.model.vertices[start..start + attribute.size]
+            })
+        };
+
+        let difference = |candidate: u32| {
+            let mut difference = 0.0;
+            for &kind in [AttributeKind::Normal, AttributeKind::TextureCoordinate].iter() {
+                if let (Some(a), Some(b)) = (attribute(vertex, kind), attribute(candidate, kind)) {
+                    difference += a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+                }
+            }
+            difference
+        };
+
+        let candidates = &self.vertices_at[position];
+        let mut best = candidates[0];
+        let mut best_difference = difference(best);
+        for &candidate in &candidates[1..] {
+            let candidate_difference = difference(candidate);
+            if candidate_difference < best_difference {
+                best = candidate;
+                best_difference = candidate_difference;
+            }
+        }
+
+        best
+    }
+}
+
+fn subtract(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0]
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(vector: [f64; 3]) -> [f64; 3] {
+    let length = dot(vector, vector).sqrt();
+    if length == 0.0 {
+        return vector;
+    }
+    [vector[0] / length, vector[1] / length, vector[2] / length]
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::{Model, ModelError, Tokens, TriangleMesh};
+
+
+/// Loads an STL file, ASCII or binary. STL only has flat-shaded triangles, so corners are only
+/// shared between triangles that face the same way.
+pub fn load_stl(path: &Path) -> Result<Model, ModelError> {
+    log!("Reading file {:?}...", path);
+    let bytes = fs::read(path).map_err(|error| ModelError::Io { path: path.to_path_buf(), error })?;
+
+    // Binary files can start with "solid" too, so trust the size the header promises first.
+    let binary_size = if bytes.len() >= 84 {
+        Some(84 + 50 * u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize)
+    } else {
+        None
+    };
+
+    let mut builder = Builder::new();
+
+    if binary_size == Some(bytes.len()) || !bytes.starts_with(b"solid") {
+        read_binary(path, &bytes, &mut builder)?;
+    } else {
+        // A binary file with a few bytes of padding on the end isn't the size it promises, so
+        // anything that doesn't read as ASCII facets gets a second chance as binary.
+        let ascii = std::str::from_utf8(&bytes).ok()
+            .filter(|text| text.lines().any(|line| line.trim_start().starts_with("facet")));
+
+        match ascii {
+            Some(text) => read_ascii(path, text, &mut builder)?,
+            None if binary_size.is_some_and(|size| bytes.len() >= size) => read_binary(path, &bytes, &mut builder)?,
+            None => return Err(ModelError::Malformed {
+                path: path.to_path_buf(),
+                reason: String::from("this starts like an ASCII STL but has no facets, and it's too short to be a binary one")
+            })
+        }
+    }
+
+    if builder.mesh.triangles.is_empty() {
+        return Err(ModelError::Malformed { path: path.to_path_buf(), reason: String::from("there are no triangles") });
+    }
+
+    log!("{} vertices and {} triangles.", builder.mesh.positions.len(), builder.mesh.triangles.len());
+
+    let mut mesh = builder.mesh;
+    mesh.normals = Some(builder.normals);
+    Ok(mesh.into_model(path))
+}
+
+fn read_binary(path: &Path, bytes: &[u8], builder: &mut Builder) -> Result<(), ModelError> {
+    if bytes.len() < 84 {
+        return Err(ModelError::Malformed {
+            path: path.to_path_buf(),
+            reason: String::from("the file is too short to be a binary STL")
+        });
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < 84 + 50 * count {
+        return Err(ModelError::Malformed {
+            path: path.to_path_buf(),
+            reason: format!("the header promises {} triangles but the file ends early", count)
+        });
+    }
+
+    let read_vector = |offset: usize| {
+        let mut vector = [0.0; 3];
+        for (component, value) in vector.iter_mut().enumerate() {
+            let start = offset + component * 4;
+            *value = f32::from_le_bytes([bytes[start], bytes[start + 1], bytes[start + 2], bytes[start + 3]]);
+        }
+        vector
+    };
+
+    builder.start_object(String::new());
+    for triangle in 0..count {
+        let offset = 84 + triangle * 50;
+        builder.add_triangle(
+            read_vector(offset),
+            [read_vector(offset + 12), read_vector(offset + 24), read_vector(offset + 36)]
+        );
+    }
+    builder.finish_object();
+
+    Ok(())
+}
+
+fn read_ascii(path: &Path, text: &str, builder: &mut Builder) -> Result<(), ModelError> {
+    let mut normal = [0.0; 3];
+    let mut corners: Vec<[f32; 3]> = vec![];
+
+    for (line_index, line) in text.lines().enumerate() {
+        let mut tokens = Tokens::new(path, line_index + 1, line);
+
+        let keyword = match tokens.next() {
+            Some(token) => token,
+            None => continue
+        };
+
+        match keyword.text {
+            "solid" => {
+                let name = match tokens.peek() {
+                    Some(name) => String::from(tokens.remainder(&name)),
+                    None => String::new()
+                };
+                builder.start_object(name);
+            }
+            "endsolid" => builder.finish_object(),
+            "facet" => {
+                let kind = tokens.next_argument("facet")?;
+                if kind.text != "normal" {
+                    return Err(ModelError::MalformedFace {
+                        location: tokens.location(kind.column),
+                        token: String::from(kind.text)
+                    });
+                }
+
+                let numbers = super::parse_numbers(tokens, 3)?;
+                normal = [numbers[0], numbers[1], numbers[2]];
+                corners.clear();
+            }
+            "vertex" => {
+                let numbers = super::parse_numbers(tokens, 3)?;
+                corners.push([numbers[0], numbers[1], numbers[2]]);
+            }
+            "endloop" => {
+                if corners.len() < 3 {
+                    return Err(ModelError::NotEnoughFacePoints {
+                        location: tokens.location(keyword.column),
+                        found: corners.len()
+                    });
+                }
+
+                for triangle in super::triangulate(&corners) {
+                    builder.add_triangle(normal, [corners[triangle[0]], corners[triangle[1]], corners[triangle[2]]]);
+                }
+            }
+            "outer" | "endfacet" => (),
+            other => log!("Unknown STL keyword: {}", other)
+        }
+    }
+
+    // Some exporters forget the closing `endsolid`.
+    builder.finish_object();
+
+    Ok(())
+}
+
+
+struct Builder {
+    mesh: TriangleMesh,
+    /// Corners by the bits of their position and normal, so facets facing the same way share them.
+    corner_indices: HashMap<[u32; 6], u32>,
+    normals: Vec<[f32; 3]>,
+    object: Option<(String, usize)>
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder {
+            mesh: TriangleMesh {
+                positions: vec![],
+                normals: None,
+                texture_coordinates: None,
+                colors: None,
+                triangles: vec![],
+                objects: vec![]
+            },
+            corner_indices: HashMap::new(),
+            normals: vec![],
+            object: None
+        }
+    }
+
+    fn start_object(&mut self, name: String) {
+        self.finish_object();
+        self.object = Some((name, self.mesh.triangles.len()));
+    }
+
+    fn finish_object(&mut self) {
+        if let Some((name, start)) = self.object.take() {
+            self.mesh.objects.push((name, start..self.mesh.triangles.len()));
+        }
+    }
+
+    fn add_triangle(&mut self, normal: [f32; 3], corners: [[f32; 3]; 3]) {
+        // Plenty of exporters write zero normals, so work it out from the corners instead.
+        let mut normal = super::normalize(normal);
+        if normal == [0.0; 3] {
+            let edge1 = [corners[1][0] - corners[0][0], corners[1][1] - corners[0][1], corners[1][2] - corners[0][2]];
+            let edge2 = [corners[2][0] - corners[0][0], corners[2][1] - corners[0][1], corners[2][2] - corners[0][2]];
+            normal = super::normalize(super::cross(edge1, edge2));
+        }
+
+        let mut triangle = [0; 3];
+        for (corner, position) in corners.iter().enumerate() {
+            let key = [
+                position[0].to_bits(), position[1].to_bits(), position[2].to_bits(),
+                normal[0].to_bits(), normal[1].to_bits(), normal[2].to_bits()
+            ];
+
+            let positions = &mut self.mesh.positions;
+            let normals = &mut self.normals;
+            triangle[corner] = *self.corner_indices.entry(key).or_insert_with(|| {
+                positions.push(*position);
+                normals.push(normal);
+                (positions.len() - 1) as u32
+            });
+        }
+
+        self.mesh.triangles.push(triangle);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use super::{cross, normalize, AttributeKind, Model};
+
+
+impl Model {
+    /// Adds a `Tangent` attribute to every vertex, worked out from the positions, normals and
+    /// texture coordinates the same way MikkTSpace does: each triangle's tangent is projected onto
+    /// the vertex's normal plane and weighted by the angle of the triangle's corner.
+    ///
+    /// Tangents have four components. The fourth is 1 or -1, and the bitangent is
+    /// `cross(normal, tangent.xyz) * tangent.w`. Vertices where mirrored texture coordinates meet
+    /// get split, so each copy can have its own sign.
+    ///
+    /// Returns false if the model doesn't have normals or texture coordinates to work from.
+    pub fn generate_tangents(&mut self) -> bool {
+        if self.layout.get(AttributeKind::Tangent).is_some() {
+            return true;
+        }
+
+        let (position, normal, texture_coordinate) = match (
+            self.layout.get(AttributeKind::Position).cloned(),
+            self.layout.get(AttributeKind::Normal).cloned(),
+            self.layout.get(AttributeKind::TextureCoordinate).cloned()
+        ) {
+            (Some(position), Some(normal), Some(texture_coordinate)) => (position, normal, texture_coordinate),
+            _ => {
+                log!("Can't generate tangents without positions, normals and texture coordinates.");
+                return false;
+            }
+        };
+
+        let stride = self.layout.stride;
+        let vertices = &self.vertices;
+        let read3 = |vertex: u32, offset: usize| {
+            let start = vertex as usize * stride + offset;
+            [vertices[start], vertices[start + 1], vertices[start + 2]]
+        };
+        let read2 = |vertex: u32, offset: usize| {
+            let start = vertex as usize * stride + offset;
+            [vertices[start], vertices[start + 1]]
+        };
+
+        // Sums of the weighted tangents for each vertex, kept apart by which way the texture is
+        // facing on the triangles that use it.
+        let mut sums: HashMap<(u32, bool), [f32; 3]> = HashMap::new();
+        let mut corner_keys: Vec<(u32, bool)> = Vec::with_capacity(self.indices.len());
+
+        for triangle in self.indices.chunks(3) {
+            if triangle.len() < 3 {
+                break;
+            }
+
+            let positions = [read3(triangle[0], position.offset), read3(triangle[1], position.offset), read3(triangle[2], position.offset)];
+            let uvs = [
+                read2(triangle[0], texture_coordinate.offset),
+                read2(triangle[1], texture_coordinate.offset),
+                read2(triangle[2], texture_coordinate.offset)
+            ];
+
+            let edge1 = subtract(positions[1], positions[0]);
+            let edge2 = subtract(positions[2], positions[0]);
+            let (s1, t1) = (uvs[1][0] - uvs[0][0], uvs[1][1] - uvs[0][1]);
+            let (s2, t2) = (uvs[2][0] - uvs[0][0], uvs[2][1] - uvs[0][1]);
+
+            // Twice the signed area of the triangle in texture space. Its sign says whether the
+            // texture is mirrored on this triangle.
+            let area = s1 * t2 - s2 * t1;
+            let face_tangent = [
+                t2 * edge1[0] - t1 * edge2[0],
+                t2 * edge1[1] - t1 * edge2[1],
+                t2 * edge1[2] - t1 * edge2[2]
+            ];
+            let face_tangent = if area > 0.0 {
+                face_tangent
+            } else if area < 0.0 {
+                scale(face_tangent, -1.0)
+            } else {
+                [0.0; 3]
+            };
+            let orientation_preserving = area >= 0.0;
+
+            for corner in 0..3 {
+                let vertex = triangle[corner];
+                let vertex_normal = read3(vertex, normal.offset);
+
+                let to_next = normalize(subtract(positions[(corner + 1) % 3], positions[corner]));
+                let to_previous = normalize(subtract(positions[(corner + 2) % 3], positions[corner]));
+                let angle = dot(to_next, to_previous).clamp(-1.0, 1.0).acos();
+
+                let projected = normalize(subtract(face_tangent, scale(vertex_normal, dot(vertex_normal, face_tangent))));
+
+                let key = (vertex, orientation_preserving);
+                let sum = sums.entry(key).or_insert([0.0; 3]);
+                for axis in 0..3 {
+                    sum[axis] += projected[axis] * angle;
+                }
+                corner_keys.push(key);
+            }
+        }
+
+        // Vertices used with both orientations get a copy for the mirrored side.
+        let vertex_count = self.vertices.len() / stride;
+        let mut new_indices: HashMap<(u32, bool), u32> = HashMap::new();
+        let mut copies: Vec<u32> = vec![];
+        for &(vertex, orientation_preserving) in corner_keys.iter() {
+            let both = sums.contains_key(&(vertex, true)) && sums.contains_key(&(vertex, false));
+            if both && !orientation_preserving && !new_indices.contains_key(&(vertex, false)) {
+                new_indices.insert((vertex, false), (vertex_count + copies.len()) as u32);
+                copies.push(vertex);
+            }
+        }
+
+        let new_stride = stride + 4;
+        let tangent_for = |vertex: u32, orientation_preserving: bool| {
+            let sum = sums.get(&(vertex, orientation_preserving)).cloned().unwrap_or([0.0; 3]);
+            let vertex_normal = read3(vertex, normal.offset);
+
+            let mut tangent = normalize(subtract(sum, scale(vertex_normal, dot(vertex_normal, sum))));
+            if tangent == [0.0; 3] {
+                tangent = any_perpendicular(vertex_normal);
+            }
+
+            [tangent[0], tangent[1], tangent[2], if orientation_preserving { 1.0 } else { -1.0 }]
+        };
+
+        let mut out: Vec<f32> = Vec::with_capacity((vertex_count + copies.len()) * new_stride);
+        for vertex in 0..vertex_count as u32 {
+            let start = vertex as usize * stride;
+            out.extend_from_slice(&self.vertices[start..start + stride]);
+
+            let orientation_preserving = sums.contains_key(&(vertex, true)) || !sums.contains_key(&(vertex, false));
+            out.extend_from_slice(&tangent_for(vertex, orientation_preserving));
+        }
+        for &vertex in &copies {
+            let start = vertex as usize * stride;
+            out.extend_from_slice(&self.vertices[start..start + stride]);
+            out.extend_from_slice(&tangent_for(vertex, false));
+        }
+
+        for (index, key) in self.indices.iter_mut().zip(corner_keys) {
+            if let Some(&new_index) = new_indices.get(&key) {
+                *index = new_index;
+            }
+        }
+
+        log!("Generated tangents, {} vertices had to be split.", copies.len());
+
+        self.vertices = out;
+        self.layout = self.layout.clone().with(AttributeKind::Tangent, 4);
+
+        true
+    }
+}
+
+fn subtract(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(vector: [f32; 3], factor: f32) -> [f32; 3] {
+    [vector[0] * factor, vector[1] * factor, vector[2] * factor]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// For vertices whose texture coordinates don't tell us anything, like ones on degenerate UVs.
+fn any_perpendicular(normal: [f32; 3]) -> [f32; 3] {
+    let axis = if normal[0].abs() < 0.9 { [1.0, 0.0, 0.0] } else { [0.0, 1.0, 0.0] };
+    normalize(cross(axis, normal))
+}
@@ -0,0 +1,209 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::program::ShaderError;
+
+
+/// A file that went into a shader. Its index in `Source::files` is the source string number the
+/// `#line` directives give it, which is what drivers put in their error messages.
+pub struct SourceFile {
+  pub path: PathBuf,
+  pub text: String,
+  /// When the file was last changed, as of when it was read.
+  pub modified: Option<SystemTime>
+}
+
+/// GLSL ready for the driver, and the files it came from.
+pub struct Source {
+  pub text: String,
+  pub files: Vec<SourceFile>
+}
+
+/// Reads a shader, pasting in the files it `#include`s and adding a `#define` for each of
+/// `defines` right after the `#version` line.
+///
+/// Includes are found relative to the file that includes them, and each file is only included
+/// once, so shared files don't need include guards. They're handled before anything else, so an
+/// `#include` inside an `#if` is always included.
+pub fn preprocess(path: &Path, defines: &[(String, String)]) -> Result<Source, ShaderError> {
+  let mut source = Source { text: String::new(), files: vec![] };
+  include(&mut source, path, None, defines)?;
+  Ok(source)
+}
+
+fn include(
+  source: &mut Source,
+  path: &Path,
+  included_from: Option<(&Path, usize)>,
+  defines: &[(String, String)]
+) -> Result<(), ShaderError> {
+  if source.files.iter().any(|file| file.path == path) {
+    return Ok(());
+  }
+
+  let modified = modified_time(path);
+  let text = fs::read_to_string(path).map_err(|error| match included_from {
+    Some((from, line)) => ShaderError::Preprocess {
+      path: from.to_path_buf(),
+      line,
+      reason: format!("couldn't include {}: {}", path.display(), error)
+    },
+    None => ShaderError::Io { path: path.to_path_buf(), error }
+  })?;
+
+  let index = source.files.len();
+  source.files.push(SourceFile { path: path.to_path_buf(), text: text.clone(), modified });
+
+  if included_from.is_some() {
+    source.text.push_str(&format!("#line 1 {}\n", index));
+  } else if !text.lines().any(|line| line.trim_start().starts_with("#version")) {
+    // Without a #version line, the defines can go right at the top.
+    write_defines(source, defines, 1);
+  }
+
+  for (line_index, line) in text.lines().enumerate() {
+    let line_number = line_index + 1;
+    let trimmed = line.trim_start();
+
+    if included_from.is_none() && trimmed.starts_with("#version") {
+      source.text.push_str(line);
+      source.text.push('\n');
+      write_defines(source, defines, line_number + 1);
+      continue;
+    }
+
+    let directive = trimmed.strip_prefix('#').map(str::trim_start);
+    if let Some(arguments) = directive.and_then(|directive| directive.strip_prefix("include")) {
+      let arguments = arguments.trim();
+      if arguments.len() < 2 || !arguments.starts_with('"') || !arguments.ends_with('"') {
+        return Err(ShaderError::Preprocess {
+          path: path.to_path_buf(),
+          line: line_number,
+          reason: String::from("#include needs a file name in quotes")
+        });
+      }
+
+      let name = &arguments[1..arguments.len() - 1];
+      let include_path = match path.parent() {
+        Some(directory) => directory.join(name),
+        None => PathBuf::from(name)
+      };
+      include(source, &include_path, Some((path, line_number)), defines)?;
+
+      // Back to where we were.
+      source.text.push_str(&format!("#line {} {}\n", line_number + 1, index));
+      continue;
+    }
+
+    source.text.push_str(line);
+    source.text.push('\n');
+  }
+
+  Ok(())
+}
+
+/// Adds the defines, then a `#line` so the next line is still `next_line` of the first file.
+fn write_defines(source: &mut Source, defines: &[(String, String)], next_line: usize) {
+  for (name, value) in defines {
+    source.text.push_str(&format!("#define {} {}\n", name, value));
+  }
+  source.text.push_str(&format!("#line {} 0\n", next_line));
+}
+
+/// When a file was last changed, or `None` if it can't be read, which also counts as a change
+/// when it comes back.
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+  fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  /// Writes each name and text to a fresh directory, and returns the directory.
+  fn write_files(files: &[(&str, &str)]) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = std::env::temp_dir().join(format!(
+      "learn_opengl-preprocessor-{}-{}",
+      std::process::id(),
+      NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+    for (name, text) in files {
+      fs::write(directory.join(name), text).unwrap();
+    }
+    directory
+  }
+
+  fn preprocess_files(files: &[(&str, &str)], defines: &[(&str, &str)]) -> Result<Source, ShaderError> {
+    let directory = write_files(files);
+    let defines: Vec<(String, String)> = defines.iter()
+      .map(|&(name, value)| (String::from(name), String::from(value)))
+      .collect();
+    let result = preprocess(&directory.join(files[0].0), &defines);
+    fs::remove_dir_all(&directory).unwrap();
+    result
+  }
+
+  #[test]
+  fn defines_go_after_the_version() {
+    let source = preprocess_files(&[("main.frag", "#version 330 core\nvoid main() {}\n")], &[("LIGHTS", "4")]).unwrap();
+    assert_eq!(source.text, "#version 330 core\n#define LIGHTS 4\n#line 2 0\nvoid main() {}\n");
+  }
+
+  #[test]
+  fn defines_go_at_the_top_without_a_version() {
+    let source = preprocess_files(&[("main.frag", "void main() {}\n")], &[("LIGHTS", "4")]).unwrap();
+    assert_eq!(source.text, "#define LIGHTS 4\n#line 1 0\nvoid main() {}\n");
+  }
+
+  #[test]
+  fn line_numbers_pick_up_after_an_include() {
+    let source = preprocess_files(&[
+      ("main.frag", "#version 330 core\n#include \"lighting.glsl\"\nvoid main() {}\n"),
+      ("lighting.glsl", "float light;\n")
+    ], &[]).unwrap();
+
+    assert_eq!(source.text, "#version 330 core\n#line 2 0\n#line 1 1\nfloat light;\n#line 3 0\nvoid main() {}\n");
+    assert_eq!(source.files.len(), 2);
+    assert!(source.files[1].path.ends_with("lighting.glsl"));
+  }
+
+  #[test]
+  fn files_are_only_included_once() {
+    let source = preprocess_files(&[
+      ("main.frag", "#include \"a.glsl\"\n#include \"b.glsl\"\n#include \"a.glsl\"\n"),
+      ("a.glsl", "float a;\n"),
+      ("b.glsl", "#include \"a.glsl\"\nfloat b;\n")
+    ], &[]).unwrap();
+
+    assert_eq!(source.text.matches("float a;").count(), 1);
+    assert_eq!(source.files.len(), 3);
+  }
+
+  #[test]
+  fn bad_includes_are_errors() {
+    let malformed = preprocess_files(&[("main.frag", "void main() {}\n#include lighting.glsl\n")], &[]);
+    match malformed {
+      Err(ShaderError::Preprocess { path, line, reason }) => {
+        assert!(path.ends_with("main.frag"));
+        assert_eq!(line, 2);
+        assert!(reason.contains("quotes"), "{}", reason);
+      }
+      other => panic!("expected a preprocessing error, got {:?}", other.map(|source| source.text))
+    }
+
+    let missing = preprocess_files(&[("main.frag", "#include \"nowhere.glsl\"\n")], &[]);
+    match missing {
+      Err(ShaderError::Preprocess { path, line, reason }) => {
+        assert!(path.ends_with("main.frag"));
+        assert_eq!(line, 1);
+        assert!(reason.contains("nowhere.glsl"), "{}", reason);
+      }
+      other => panic!("expected a preprocessing error, got {:?}", other.map(|source| source.text))
+    }
+  }
+}
@@ -1,54 +1,364 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::ffi::CString;
-use std::fs;
+use std::fmt;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use crate::preprocessor::{self, Source};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShaderStage {
+  Vertex,
+  Fragment
+}
+
+impl ShaderStage {
+  fn gl_type(self) -> u32 {
+    match self {
+      ShaderStage::Vertex => gl::VERTEX_SHADER,
+      ShaderStage::Fragment => gl::FRAGMENT_SHADER
+    }
+  }
+
+  fn extension(self) -> &'static str {
+    match self {
+      ShaderStage::Vertex => "vert",
+      ShaderStage::Fragment => "frag"
+    }
+  }
+
+  fn source_path(self, shader_name: &str) -> PathBuf {
+    Path::new("./assets").join(format!("{}.{}", shader_name, self.extension()))
+  }
+}
+
+impl fmt::Display for ShaderStage {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ShaderStage::Vertex => write!(f, "vertex"),
+      ShaderStage::Fragment => write!(f, "fragment")
+    }
+  }
+}
+
+/// One line of a driver's info log. Drivers all write these differently, but most start with
+/// the source string and line number, which we pull out so errors can point at the file.
+#[derive(Debug, Clone)]
+pub struct LogMessage {
+  /// The file the message is about, going by the source string number.
+  pub path: Option<PathBuf>,
+  pub line: Option<usize>,
+  pub message: String,
+  /// The line of source the message is about, if we know it.
+  pub code: Option<String>
+}
+
+impl LogMessage {
+  fn parse_log(log: &str, source: Option<&Source>) -> Vec<LogMessage> {
+    log.lines().map(str::trim).filter(|line| !line.is_empty()).map(|line| {
+      match split_location(line) {
+        Some((source_number, line_number, message)) => {
+          let file = source.and_then(|source| source.files.get(source_number));
+          LogMessage {
+            path: file.map(|file| file.path.clone()),
+            line: Some(line_number),
+            message,
+            code: file
+              .and_then(|file| file.text.lines().nth(line_number.checked_sub(1)?))
+              .map(|code| String::from(code.trim()))
+          }
+        }
+        None => LogMessage { path: None, line: None, message: String::from(line), code: None }
+      }
+    }).collect()
+  }
+}
+
+/// Finds a location like `0(12)` (NVIDIA) or `0:12` (Mesa, AMD, Intel and Apple) in an info log
+/// line, and returns the source string and line numbers along with the rest of the line.
+fn split_location(line: &str) -> Option<(usize, usize, String)> {
+  let bytes = line.as_bytes();
+  let digits_from = |start: usize| {
+    let mut end = start;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+      end += 1;
+    }
+    end
+  };
+
+  let mut start = 0;
+  while start < bytes.len() {
+    let at_word_start = start == 0 || bytes[start - 1] == b' ';
+    let source_end = digits_from(start);
+
+    if at_word_start && source_end > start && source_end < bytes.len() {
+      let separator = bytes[source_end];
+      let line_start = source_end + 1;
+      let line_end = digits_from(line_start);
+
+      let closed = match separator {
+        b'(' => line_end < bytes.len() && bytes[line_end] == b')',
+        b':' => true,
+        _ => false
+      };
+
+      // Numbers too big to be a location are just part of the message.
+      let numbers = match (line[start..source_end].parse(), line[line_start..line_end].parse()) {
+        (Ok(source_number), Ok(line_number)) if closed => Some((source_number, line_number)),
+        _ => None
+      };
+
+      if let Some((source_number, line_number)) = numbers {
+        let mut rest = &line[line_end..];
+        if separator == b'(' {
+          rest = &rest[1..];
+        }
+
+        // Mesa adds a column, like "0:12(5)".
+        if rest.starts_with('(') {
+          if let Some(close) = rest.find(')') {
+            rest = &rest[close + 1..];
+          }
+        }
+
+        let rest = rest.trim_start_matches([':', ' ']);
+        let message = format!("{}{}", &line[..start], rest);
+        return Some((source_number, line_number, message));
+      }
+    }
+
+    start = source_end.max(start + 1);
+  }
+
+  None
+}
+
+#[derive(Debug)]
+pub enum ShaderError {
+  Io { path: PathBuf, error: io::Error },
+  /// A bad `#include`, found before the driver ever sees the source.
+  Preprocess { path: PathBuf, line: usize, reason: String },
+  Compile { stage: ShaderStage, path: PathBuf, log: Vec<LogMessage> },
+  Link { name: String, log: Vec<LogMessage> }
+}
+
+impl fmt::Display for ShaderError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ShaderError::Io { path, error } => {
+        write!(f, "{}: {}", path.display(), error)
+      }
+      ShaderError::Preprocess { path, line, reason } => {
+        write!(f, "{}:{}: {}", path.display(), line, reason)
+      }
+      ShaderError::Compile { stage, path, log } => {
+        write!(f, "couldn't compile {} shader {}", stage, path.display())?;
+        for entry in log {
+          // Messages about an included file point at that file instead.
+          let entry_path = entry.path.as_ref().unwrap_or(path);
+          match entry.line {
+            Some(line) => write!(f, "\n{}:{}: {}", entry_path.display(), line, entry.message)?,
+            None => write!(f, "\n{}: {}", entry_path.display(), entry.message)?
+          }
+          if let Some(code) = &entry.code {
+            write!(f, "\n    {}", code)?;
+          }
+        }
+        Ok(())
+      }
+      ShaderError::Link { name, log } => {
+        write!(f, "couldn't link shader program \"{}\"", name)?;
+        for entry in log {
+          write!(f, "\n{}", entry.message)?;
+        }
+        Ok(())
+      }
+    }
+  }
+}
+
+impl Error for ShaderError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ShaderError::Io { error, .. } => Some(error),
+      _ => None
+    }
+  }
+}
+
+
+/// What the driver told us about an active uniform after linking.
+struct Uniform {
+  location: i32,
+  /// The GL type, like `gl::FLOAT_VEC3`.
+  kind: u32,
+  /// How many elements it has, if it's an array.
+  size: i32
+}
+
+/// Types `set_signed_int` can set: samplers are set by texture unit.
+const INT_TYPES: &[u32] = &[
+  gl::INT, gl::BOOL,
+  gl::SAMPLER_1D, gl::SAMPLER_2D, gl::SAMPLER_3D, gl::SAMPLER_CUBE,
+  gl::SAMPLER_2D_SHADOW, gl::SAMPLER_2D_ARRAY, gl::SAMPLER_CUBE_SHADOW, gl::SAMPLER_2D_MULTISAMPLE
+];
 
 pub struct Program {
-  id: u32
+  id: u32,
+  name: String,
+  uniforms: HashMap<String, Uniform>,
+  /// Uniforms we've already complained about, so each one only gets one warning.
+  warned: RefCell<HashSet<&'static str>>,
+  /// Added to the top of both shaders, as `#define name value`.
+  defines: Vec<(String, String)>,
+  /// The files the program was built from, includes and all, and when they were last changed,
+  /// for reloading.
+  sources: Sources
 }
 
+/// Files to watch, and when they were last changed.
+type Sources = Vec<(PathBuf, Option<SystemTime>)>;
+
 impl Program {
-  pub fn new(shader_name: &str) -> Program {
-    let vertex_shader_source = fs::read_to_string(format!("./assets/{}.vert", shader_name))
-        .expect(&format!("Failed to load {} vertex shader!", shader_name));
-    let vertex_shader_source = CString::new(vertex_shader_source).unwrap();
-    let vertex_shader = Program::create_shader(vertex_shader_source, gl::VERTEX_SHADER);
-
-    let fragment_shader_source = fs::read_to_string(format!("./assets/{}.frag", shader_name))
-        .expect(&format!("Failed to load {} fragment shader!", shader_name));
-    let fragment_shader_source = CString::new(fragment_shader_source).unwrap();
-    let fragment_shader = Program::create_shader(fragment_shader_source, gl::FRAGMENT_SHADER);
-
-    let shader_program = unsafe { gl::CreateProgram() };
-    unsafe {
-        gl::AttachShader(shader_program, vertex_shader);
-        gl::AttachShader(shader_program, fragment_shader);
-        gl::LinkProgram(shader_program);
-
-        // Check for errors.
-        let mut success = 1;
-        gl::GetProgramiv(shader_program, gl::COMPILE_STATUS, &mut success);
-        if success == 0 {
-            let mut error_length = 0;
-            gl::GetProgramiv(shader_program, gl::INFO_LOG_LENGTH, &mut error_length);
-            let error_string = CString::from_vec_unchecked(
-                std::iter::repeat(b' ').take(error_length as usize).collect::<Vec<u8>>()
-            );
-            gl::GetProgramInfoLog(
-                shader_program,
-                error_length,
-                std::ptr::null_mut(),
-                error_string.as_ptr() as *mut _
-            );
-
-            let error = error_string.to_string_lossy().into_owned();
-            panic!("Error linking shader program: {}", error);
+  /// Compiles and links `assets/<shader_name>.vert` and `assets/<shader_name>.frag`.
+  pub fn new(shader_name: &str) -> Result<Program, ShaderError> {
+    Program::with_defines(shader_name, &[])
+  }
+
+  /// Builds the variant of a shader with `features` turned on, along with `defines`.
+  pub fn with_features(shader_name: &str, features: Features, defines: &[(&str, &str)]) -> Result<Program, ShaderError> {
+    let mut all_defines: Vec<(&str, &str)> = features.defines().map(|name| (name, "1")).collect();
+    all_defines.extend_from_slice(defines);
+    Program::with_defines(shader_name, &all_defines)
+  }
+
+  /// Like `new`, but with a `#define` for each name and value in `defines`.
+  pub fn with_defines(shader_name: &str, defines: &[(&str, &str)]) -> Result<Program, ShaderError> {
+    let defines = defines.iter()
+      .map(|&(name, value)| (String::from(name), String::from(value)))
+      .collect::<Vec<_>>();
+
+    let mut sources = vec![];
+    let shader_program = Program::link(shader_name, &defines, &mut sources)?;
+    Ok(Program {
+      id: shader_program,
+      name: String::from(shader_name),
+      uniforms: unsafe { reflect_uniforms(shader_program) },
+      warned: RefCell::new(HashSet::new()),
+      defines,
+      sources
+    })
+  }
+
+  /// Rebuilds the program if any of its files have changed since it was last built. Returns
+  /// whether it did, in which case every uniform is back to its default and needs setting again.
+  /// If the new version doesn't build, the error is printed and the old program stays in use.
+  pub fn reload_if_changed(&mut self) -> bool {
+    self.sources_changed() && self.reload()
+  }
+
+  /// Whether any of the program's files have changed since it was last built or this was last
+  /// asked. Only says so once for each change.
+  fn sources_changed(&mut self) -> bool {
+    let mut changed = false;
+    for (path, modified) in &mut self.sources {
+      let now = preprocessor::modified_time(path);
+      if now != *modified {
+        *modified = now;
+        changed = true;
+      }
+    }
+    changed
+  }
+
+  fn reload(&mut self) -> bool {
+    println!("Reloading shader program \"{}\"...", self.name);
+    let mut sources = vec![];
+    match Program::link(&self.name, &self.defines, &mut sources) {
+      Ok(shader_program) => unsafe {
+        gl::DeleteProgram(self.id);
+        self.id = shader_program;
+        self.sources = sources;
+        self.uniforms = reflect_uniforms(shader_program);
+        self.warned.borrow_mut().clear();
+        true
+      },
+      Err(error) => {
+        eprintln!("Couldn't reload shader program \"{}\", keeping the old one: {}", self.name, error);
+
+        // Keep an eye on anything the broken version added too, like a new include, so fixing
+        // it brings the reload back.
+        for (path, modified) in sources {
+          watch(&mut self.sources, path, modified);
         }
+        false
+      }
+    }
+  }
+
+  /// Builds the program. Every file that went into it is added to `sources`, even if it doesn't
+  /// build, as far as it got.
+  fn link(shader_name: &str, defines: &[(String, String)], sources: &mut Sources) -> Result<u32, ShaderError> {
+    let vertex_source = Program::preprocess(shader_name, ShaderStage::Vertex, defines, sources)?;
+    let fragment_source = Program::preprocess(shader_name, ShaderStage::Fragment, defines, sources)?;
 
-        gl::DeleteShader(vertex_shader);
-        gl::DeleteShader(fragment_shader);
+    let vertex_shader = Program::create_shader(&vertex_source, ShaderStage::Vertex)?;
+    let fragment_shader = match Program::create_shader(&fragment_source, ShaderStage::Fragment) {
+      Ok(shader) => shader,
+      Err(error) => {
+        unsafe { gl::DeleteShader(vertex_shader); }
+        return Err(error);
+      }
+    };
+
+    unsafe {
+      let shader_program = gl::CreateProgram();
+      gl::AttachShader(shader_program, vertex_shader);
+      gl::AttachShader(shader_program, fragment_shader);
+      gl::LinkProgram(shader_program);
+
+      // The program keeps what it needs, linked or not.
+      gl::DeleteShader(vertex_shader);
+      gl::DeleteShader(fragment_shader);
+
+      // Check for errors.
+      let mut success = 1;
+      gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut success);
+      if success == 0 {
+        let log = info_log(shader_program, gl::GetProgramiv, gl::GetProgramInfoLog);
+        gl::DeleteProgram(shader_program);
+
+        return Err(ShaderError::Link {
+          name: String::from(shader_name),
+          log: LogMessage::parse_log(&log, None)
+        });
+      }
+
+      Ok(shader_program)
     }
+  }
 
-    Program { id: shader_program }
+  fn preprocess(shader_name: &str, stage: ShaderStage, defines: &[(String, String)], sources: &mut Sources) -> Result<Source, ShaderError> {
+    match preprocessor::preprocess(&stage.source_path(shader_name), defines) {
+      Ok(source) => {
+        for file in &source.files {
+          watch(sources, file.path.clone(), file.modified);
+        }
+        Ok(source)
+      }
+      Err(error) => {
+        // The file with the problem in it, which might not have been read yet.
+        if let ShaderError::Io { path, .. } | ShaderError::Preprocess { path, .. } = &error {
+          watch(sources, path.clone(), preprocessor::modified_time(path));
+        }
+        Err(error)
+      }
+    }
   }
 
   pub fn set_used(&self) {
@@ -58,65 +368,326 @@ impl Program {
   }
 
   pub fn set_signed_int(&self, uniform_name: &'static str, int: i32) {
-    let uniform_location = self.get_uniform_location(uniform_name);
-    unsafe {
-      gl::Uniform1i(uniform_location, int);
+    if let Some(uniform_location) = self.get_uniform_location(uniform_name, INT_TYPES, "an int") {
+      unsafe {
+        gl::Uniform1i(uniform_location, int);
+      }
     }
   }
 
   pub fn set_float(&self, uniform_name: &'static str, float: f32) {
-    let uniform_location = self.get_uniform_location(uniform_name);
-    unsafe {
-      gl::Uniform1f(uniform_location, float);
+    if let Some(uniform_location) = self.get_uniform_location(uniform_name, &[gl::FLOAT, gl::BOOL], "a float") {
+      unsafe {
+        gl::Uniform1f(uniform_location, float);
+      }
     }
   }
 
   pub fn set_vector3(&self, uniform_name: &'static str, vector: glm::Vector3<f32>) {
-    let uniform_location = self.get_uniform_location(uniform_name);
-    unsafe {
-      gl::Uniform3fv(uniform_location, 1, vector.as_array().as_ptr());
+    if let Some(uniform_location) = self.get_uniform_location(uniform_name, &[gl::FLOAT_VEC3], "a vec3") {
+      unsafe {
+        gl::Uniform3fv(uniform_location, 1, vector.as_array().as_ptr());
+      }
     }
   }
 
   pub fn set_matrix(&self, uniform_name: &'static str, matrix: glm::Matrix4<f32>) {
-    let uniform_location = self.get_uniform_location(uniform_name);
-    unsafe {
-      gl::UniformMatrix4fv(uniform_location, 1, gl::FALSE, matrix.as_array()[0].as_array().as_ptr());
+    if let Some(uniform_location) = self.get_uniform_location(uniform_name, &[gl::FLOAT_MAT4], "a mat4") {
+      unsafe {
+        gl::UniformMatrix4fv(uniform_location, 1, gl::FALSE, matrix.as_array()[0].as_array().as_ptr());
+      }
     }
   }
 
-  fn get_uniform_location(&self, uniform_name: &'static str) -> i32 {
-    let uniform_cstring = CString::new(uniform_name).unwrap();
-    unsafe { gl::GetUniformLocation(self.id, uniform_cstring.as_ptr()) }
+  /// Looks up a uniform from the ones found when the program was linked, and checks it can hold
+  /// one of `types`. Warns the first time a name is wrong, then quietly ignores it.
+  fn get_uniform_location(&self, uniform_name: &'static str, types: &[u32], type_name: &str) -> Option<i32> {
+    let problem = match self.uniforms.get(uniform_name) {
+      Some(uniform) if types.contains(&uniform.kind) => return Some(uniform.location),
+      Some(uniform) if uniform.size > 1 => {
+        format!("can't be set to {} (it's an array of {} with GL type 0x{:X})", type_name, uniform.size, uniform.kind)
+      }
+      Some(uniform) => format!("can't be set to {} (its GL type is 0x{:X})", type_name, uniform.kind),
+      None => String::from("isn't an active uniform (it might have been optimized out)")
+    };
+
+    if self.warned.borrow_mut().insert(uniform_name) {
+      eprintln!("Warning: uniform \"{}\" in shader program \"{}\" {}", uniform_name, self.name, problem);
+    }
+    None
   }
 
-  fn create_shader(source: CString, shader_type: u32) -> u32 {
+  fn create_shader(source: &Source, stage: ShaderStage) -> Result<u32, ShaderError> {
+    let path = source.files[0].path.clone();
+    let source_cstring = CString::new(source.text.as_str()).map_err(|error| ShaderError::Io {
+      path: path.clone(),
+      error: io::Error::new(io::ErrorKind::InvalidData, error)
+    })?;
+
     unsafe {
-        let shader_id = gl::CreateShader(shader_type);
-        gl::ShaderSource(shader_id, 1, &source.as_ptr(), std::ptr::null());
-        gl::CompileShader(shader_id);
-
-        // Check for errors.
-        let mut success = 1;
-        gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
-        if success == 0 {
-            let mut error_length = 0;
-            gl::GetShaderiv(shader_id, gl::INFO_LOG_LENGTH, &mut error_length);
-            let error_string = CString::from_vec_unchecked(
-                std::iter::repeat(b' ').take(error_length as usize).collect::<Vec<u8>>()
-            );
-            gl::GetShaderInfoLog(
-                shader_id,
-                error_length,
-                std::ptr::null_mut(),
-                error_string.as_ptr() as *mut _
-            );
-
-            let error = error_string.to_string_lossy().into_owned();
-            panic!("Error compiling shader: {}", error);
+      let shader_id = gl::CreateShader(stage.gl_type());
+      gl::ShaderSource(shader_id, 1, &source_cstring.as_ptr(), std::ptr::null());
+      gl::CompileShader(shader_id);
+
+      // Check for errors.
+      let mut success = 1;
+      gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
+      if success == 0 {
+        let log = info_log(shader_id, gl::GetShaderiv, gl::GetShaderInfoLog);
+        gl::DeleteShader(shader_id);
+
+        return Err(ShaderError::Compile { stage, path, log: LogMessage::parse_log(&log, Some(source)) });
+      }
+
+      Ok(shader_id)
+    }
+  }
+}
+
+/// Optional parts of a shader, each turned on with a `#define` like `HAS_DIFFUSE_MAP`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Features(u32);
+
+impl Features {
+  pub const NONE: Features = Features(0);
+  pub const DIFFUSE_MAP: Features = Features(1 << 0);
+  pub const NORMAL_MAP: Features = Features(1 << 1);
+
+  const DEFINES: &'static [(Features, &'static str)] = &[
+    (Features::DIFFUSE_MAP, "HAS_DIFFUSE_MAP"),
+    (Features::NORMAL_MAP, "HAS_NORMAL_MAP")
+  ];
+
+  pub fn contains(self, features: Features) -> bool {
+    self.0 & features.0 == features.0
+  }
+
+  fn defines(self) -> impl Iterator<Item = &'static str> {
+    Features::DEFINES.iter()
+      .filter(move |&&(feature, _)| self.contains(feature))
+      .map(|&(_, name)| name)
+  }
+}
+
+impl BitOr for Features {
+  type Output = Features;
+
+  fn bitor(self, features: Features) -> Features {
+    Features(self.0 | features.0)
+  }
+}
+
+impl BitOrAssign for Features {
+  fn bitor_assign(&mut self, features: Features) {
+    self.0 |= features.0;
+  }
+}
+
+impl fmt::Display for Features {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let names: Vec<&str> = self.defines().collect();
+    if names.is_empty() {
+      write!(f, "no features")
+    } else {
+      write!(f, "{}", names.join(", "))
+    }
+  }
+}
+
+/// Every variant of a shader that's been asked for. Each one is built the first time it's
+/// needed, and reloaded along with the rest when its files change.
+pub struct ShaderVariants {
+  name: String,
+  defines: Vec<(String, String)>,
+  /// Sets the uniforms that don't change, whenever a variant is built or reloaded.
+  setup: Setup,
+  programs: HashMap<Features, Program>,
+  /// Variants that didn't build, so they aren't tried again every frame. They get another go
+  /// when the shader's files change.
+  failed: HashSet<Features>
+}
+
+/// Sets up a freshly built variant.
+type Setup = Box<dyn Fn(&Program, Features)>;
+
+impl ShaderVariants {
+  /// Builds the variant with no features straight away, since it's what the others fall back
+  /// to, so this fails if that does.
+  pub fn new<F>(shader_name: &str, defines: &[(&str, &str)], setup: F) -> Result<ShaderVariants, ShaderError>
+    where F: Fn(&Program, Features) + 'static
+  {
+    let program = Program::with_features(shader_name, Features::NONE, defines)?;
+    setup(&program, Features::NONE);
+
+    let mut programs = HashMap::new();
+    programs.insert(Features::NONE, program);
+
+    Ok(ShaderVariants {
+      name: String::from(shader_name),
+      defines: defines.iter().map(|&(name, value)| (String::from(name), String::from(value))).collect(),
+      setup: Box::new(setup),
+      programs,
+      failed: HashSet::new()
+    })
+  }
+
+  /// The variant with `features`, building it if this is the first time it's been asked for.
+  /// If it doesn't build, the error is printed and the variant with no features is used.
+  pub fn get(&mut self, features: Features) -> &Program {
+    if !self.programs.contains_key(&features) && !self.failed.contains(&features) {
+      let defines: Vec<(&str, &str)> = self.defines.iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+      match Program::with_features(&self.name, features, &defines) {
+        Ok(program) => {
+          (self.setup)(&program, features);
+          self.programs.insert(features, program);
         }
+        Err(error) => {
+          eprintln!("Couldn't build shader program \"{}\" with {}: {}", self.name, features, error);
+          self.failed.insert(features);
+        }
+      }
+    }
+
+    match self.programs.get(&features) {
+      Some(program) => program,
+      None => &self.programs[&Features::NONE]
+    }
+  }
+
+  /// Reloads every variant whose files have changed, and sets them up again.
+  pub fn reload_if_changed(&mut self) {
+    let mut changed = false;
+    for (&features, program) in &mut self.programs {
+      if program.sources_changed() {
+        changed = true;
+        if program.reload() {
+          (self.setup)(program, features);
+        }
+      }
+    }
+
+    if changed {
+      self.failed.clear();
+    }
+  }
+}
+
+/// Adds a file to `sources`, unless it's already there.
+fn watch(sources: &mut Sources, path: PathBuf, modified: Option<SystemTime>) {
+  if !sources.iter().any(|(watched, _)| *watched == path) {
+    sources.push((path, modified));
+  }
+}
+
+/// Finds every active uniform in a linked program. Arrays can be set through their name, their
+/// first element or any other element, so they're listed under all of those.
+unsafe fn reflect_uniforms(program: u32) -> HashMap<String, Uniform> {
+  let mut uniforms = HashMap::new();
+
+  let mut count = 0;
+  gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+  let mut max_length = 0;
+  gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_length);
+
+  let mut name_buffer = vec![0u8; max_length.max(1) as usize];
+  for index in 0..count.max(0) as u32 {
+    let mut length = 0;
+    let mut size = 0;
+    let mut kind = 0;
+    gl::GetActiveUniform(
+      program,
+      index,
+      name_buffer.len() as i32,
+      &mut length,
+      &mut size,
+      &mut kind,
+      name_buffer.as_mut_ptr() as *mut _
+    );
+
+    let name = String::from_utf8_lossy(&name_buffer[..length.max(0) as usize]).into_owned();
+    let location = uniform_location(program, &name);
+
+    // Uniforms in blocks don't have locations.
+    if location < 0 {
+      continue;
+    }
 
-        return shader_id;
+    if let Some(base) = name.strip_suffix("[0]") {
+      for element in 1..size {
+        let element_name = format!("{}[{}]", base, element);
+        let element_location = uniform_location(program, &element_name);
+        uniforms.insert(element_name, Uniform { location: element_location, kind, size: 1 });
+      }
+      uniforms.insert(String::from(base), Uniform { location, kind, size });
     }
+
+    uniforms.insert(name, Uniform { location, kind, size });
+  }
+
+  uniforms
+}
+
+unsafe fn uniform_location(program: u32, name: &str) -> i32 {
+  match CString::new(name) {
+    Ok(name) => gl::GetUniformLocation(program, name.as_ptr()),
+    Err(_) => -1
+  }
+}
+
+/// Reads the info log of a shader or program, given the functions for whichever it is.
+unsafe fn info_log(
+  id: u32,
+  get_parameter: unsafe fn(u32, u32, *mut i32),
+  get_log: unsafe fn(u32, i32, *mut i32, *mut gl::types::GLchar)
+) -> String {
+  let mut log_length = 0;
+  get_parameter(id, gl::INFO_LOG_LENGTH, &mut log_length);
+
+  let mut log = vec![0u8; log_length.max(1) as usize];
+  let mut written = 0;
+  get_log(id, log.len() as i32, &mut written, log.as_mut_ptr() as *mut _);
+  log.truncate(written.max(0) as usize);
+
+  String::from_utf8_lossy(&log).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn nvidia_locations_are_found() {
+    assert_eq!(
+      split_location("0(12) : error C0000: syntax error, unexpected '}'"),
+      Some((0, 12, String::from("error C0000: syntax error, unexpected '}'")))
+    );
+  }
+
+  #[test]
+  fn mesa_locations_are_found() {
+    assert_eq!(
+      split_location("1:12(5): error: `colour' undeclared"),
+      Some((1, 12, String::from("error: `colour' undeclared")))
+    );
+  }
+
+  #[test]
+  fn intel_and_amd_locations_are_found() {
+    assert_eq!(
+      split_location("ERROR: 0:12: 'colour' : undeclared identifier"),
+      Some((0, 12, String::from("ERROR: 'colour' : undeclared identifier")))
+    );
+  }
+
+  #[test]
+  fn numbers_that_are_too_big_are_skipped() {
+    assert_eq!(
+      split_location("99999999999999999999:1 0:12: error"),
+      Some((0, 12, String::from("99999999999999999999:1 error")))
+    );
+    assert_eq!(split_location("error: 99999999999999999999:1"), None);
   }
 }
@@ -0,0 +1,703 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use learn_opengl::model_loader;
+use proptest::prelude::*;
+
+
+/// A fresh directory for each test case, so cases can run side by side.
+fn scratch_directory() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let directory = std::env::temp_dir().join(format!(
+        "learn_opengl-test-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+/// Lines that look enough like OBJ to get past the first token, with indices and numbers that
+/// are often out of range.
+fn obj_line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "v( -?[0-9]{1,3}(\\.[0-9]{1,2})?){0,5}",
+        "vt( -?[0-9](\\.[0-9])?){0,3}",
+        "vn( -?[0-9](\\.[0-9])?){0,4}",
+        "[flp]( -?[0-9]{1,2}(/-?[0-9]{0,2}){0,3}){0,6}",
+        "(o|g|s|usemtl|mtllib)( [a-z0-9]{0,3}){0,2}",
+        "[ -~]{0,20}"
+    ]
+}
+
+fn material_line() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "newmtl( [a-z]{0,3}){0,2}",
+        "(Ka|Kd|Ks|Ke|Tf|Ns|Ni|d|Tr|illum)( -?[0-9](\\.[0-9])?| -halo| x){0,4}",
+        "(map_Kd|map_Bump|disp)( -(o|s|t|bm|clamp|blendu|mm|imfchan)| on| off| -?[0-9]| [a-z]{1,3}\\.png){0,5}",
+        "[ -~]{0,20}"
+    ]
+}
+
+/// A triangle mesh and some materials to draw it with, as an OBJ file and an MTL file.
+#[derive(Debug)]
+struct Scene {
+    positions: Vec<[f32; 3]>,
+    triangles: Vec<([usize; 3], usize)>,
+    colors: Vec<[f32; 3]>
+}
+
+fn scene() -> impl Strategy<Value = Scene> {
+    (3usize..20, 1usize..4).prop_flat_map(|(position_count, material_count)| {
+        (
+            prop::collection::vec(prop::array::uniform3(-100.0f32..100.0), position_count),
+            prop::collection::vec((prop::array::uniform3(0..position_count), 0..material_count), 1..30),
+            prop::collection::vec(prop::array::uniform3(0.0f32..1.0), material_count)
+        )
+    }).prop_map(|(positions, triangles, colors)| Scene { positions, triangles, colors })
+}
+
+impl Scene {
+    fn write(&self, directory: &Path) -> PathBuf {
+        let mut obj = String::from("mtllib scene.mtl\n");
+        for position in &self.positions {
+            writeln!(obj, "v {} {} {}", position[0], position[1], position[2]).unwrap();
+        }
+
+        let mut current_material = None;
+        for &(triangle, material) in &self.triangles {
+            if current_material != Some(material) {
+                writeln!(obj, "usemtl material{}", material).unwrap();
+                current_material = Some(material);
+            }
+            writeln!(obj, "f {} {} {}", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1).unwrap();
+        }
+
+        let mut mtl = String::new();
+        for (index, color) in self.colors.iter().enumerate() {
+            writeln!(mtl, "newmtl material{}", index).unwrap();
+            writeln!(mtl, "Kd {} {} {}", color[0], color[1], color[2]).unwrap();
+        }
+
+        let path = directory.join("scene.obj");
+        fs::write(&path, obj).unwrap();
+        fs::write(directory.join("scene.mtl"), mtl).unwrap();
+        path
+    }
+}
+
+proptest! {
+    #[test]
+    fn obj_parser_never_panics(lines in prop::collection::vec(obj_line(), 0..20)) {
+        let text = lines.join("\n");
+        let _ = model_loader::read_obj(text.as_bytes(), Path::new("/nonexistent/test.obj"));
+    }
+
+    #[test]
+    fn material_parser_never_panics(lines in prop::collection::vec(material_line(), 0..20)) {
+        let text = lines.join("\n");
+        let _ = model_loader::read_material_file(text.as_bytes(), Path::new("/nonexistent/test.mtl"));
+    }
+
+    #[test]
+    fn obj_survives_a_round_trip(scene in scene()) {
+        let directory = scratch_directory();
+        let model = model_loader::import_model(&scene.write(&directory)).unwrap();
+
+        let saved_path = directory.join("saved.obj");
+        model_loader::save_obj(&model, &saved_path).unwrap();
+        let saved = model_loader::import_model(&saved_path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        prop_assert_eq!(&saved.vertices, &model.vertices);
+        prop_assert_eq!(&saved.indices, &model.indices);
+        prop_assert_eq!(saved.indices.len(), scene.triangles.len() * 3);
+
+        let ranges = |model: &model_loader::Model| -> Vec<(usize, usize, usize)> {
+            model.submeshes.iter().map(|submesh| (submesh.material_index, submesh.start, submesh.count)).collect()
+        };
+        prop_assert_eq!(ranges(&saved), ranges(&model));
+
+        for (saved_material, material) in saved.materials.iter().zip(&model.materials) {
+            prop_assert_eq!(&saved_material.name, &material.name);
+            prop_assert_eq!(&saved_material.diffuse_color, &material.diffuse_color);
+        }
+    }
+
+    #[test]
+    fn reading_from_memory_matches_reading_the_file(scene in scene()) {
+        let directory = scratch_directory();
+        let path = scene.write(&directory);
+        let from_file = model_loader::import_model(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let from_memory = model_loader::read_obj(&bytes[..], &path).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        prop_assert_eq!(&from_memory.vertices, &from_file.vertices);
+        prop_assert_eq!(&from_memory.indices, &from_file.indices);
+    }
+}
+
+#[test]
+fn loads_everything_from_memory() {
+    let mut resolver = model_loader::MemoryResolver::new();
+    resolver.add("memory/scene.mtl", "newmtl red\nKd 1 0 0\nmap_Kd textures\\red.png\n");
+    resolver.add("memory/textures/red.png", &b"not really a PNG"[..]);
+
+    let obj = "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n";
+    let model = model_loader::read_obj_with(obj.as_bytes(), Path::new("memory/scene.obj"), &resolver).unwrap();
+
+    assert_eq!(model.materials.len(), 1);
+    assert_eq!(model.materials[0].diffuse_color, vec![1.0, 0.0, 0.0]);
+
+    let map = model.materials[0].diffuse_map.as_ref().unwrap();
+    assert_eq!(map.path, Path::new("memory/textures/red.png"));
+    assert_eq!(map.data.as_deref(), Some(&b"not really a PNG"[..]));
+}
+
+#[test]
+fn missing_material_files_are_reported() {
+    let resolver = model_loader::MemoryResolver::new();
+    match model_loader::read_obj_with("mtllib nowhere.mtl\n".as_bytes(), Path::new("memory/scene.obj"), &resolver) {
+        Ok(_) => panic!("loaded a model whose MTL file doesn't exist"),
+        Err(error) => assert!(error.to_string().contains("nowhere.mtl"), "{}", error)
+    }
+}
+
+
+/// A glTF file with one mesh, reading from a buffer with a triangle's positions followed by its
+/// normals, saved next to it. `accessors` and `attributes` say how the buffer is read.
+fn write_gltf(directory: &Path, accessors: serde_json::Value, attributes: serde_json::Value) -> PathBuf {
+    let data: Vec<u8> = [
+        0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+        0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0
+    ].iter().flat_map(|value| value.to_le_bytes()).collect();
+    fs::write(directory.join("triangle.bin"), &data).unwrap();
+
+    let json = serde_json::json!({
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0 }],
+        "meshes": [{ "primitives": [{ "attributes": attributes }] }],
+        "buffers": [{ "uri": "triangle.bin", "byteLength": data.len() }],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 36 }
+        ],
+        "accessors": accessors
+    });
+
+    let path = directory.join("triangle.gltf");
+    fs::write(&path, json.to_string()).unwrap();
+    path
+}
+
+fn vec3_accessor(view: usize, count: usize) -> serde_json::Value {
+    serde_json::json!({ "bufferView": view, "componentType": 5126, "count": count, "type": "VEC3" })
+}
+
+#[test]
+fn gltf_triangles_load() {
+    let directory = scratch_directory();
+    let path = write_gltf(
+        &directory,
+        serde_json::json!([vec3_accessor(0, 3), vec3_accessor(1, 3)]),
+        serde_json::json!({ "POSITION": 0, "NORMAL": 1 })
+    );
+    let model = model_loader::import_model(&path);
+    fs::remove_dir_all(&directory).unwrap();
+
+    let model = match model {
+        Ok(model) => model,
+        Err(error) => panic!("{}", error)
+    };
+    assert_eq!(model.indices.len(), 3);
+    assert_eq!(model.missing_normals, 0);
+
+    let normal = model.layout.get(model_loader::AttributeKind::Normal).unwrap().offset;
+    let stride = model.layout.stride;
+    for vertex in 0..3 {
+        assert_eq!(&model.vertices[vertex * stride + normal..vertex * stride + normal + 3], &[0.0, 0.0, 1.0]);
+    }
+}
+
+#[test]
+fn gltf_attributes_must_match_the_positions() {
+    let short_normals = serde_json::json!([vec3_accessor(0, 3), vec3_accessor(1, 2)]);
+    let flat_normals = serde_json::json!([
+        vec3_accessor(0, 3),
+        { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" }
+    ]);
+    let short_texture_coordinates = serde_json::json!([
+        vec3_accessor(0, 3),
+        { "bufferView": 1, "componentType": 5126, "count": 1, "type": "VEC2" }
+    ]);
+
+    for (accessors, attribute) in [
+        (short_normals, "NORMAL"),
+        (flat_normals, "NORMAL"),
+        (short_texture_coordinates, "TEXCOORD_0")
+    ] {
+        let directory = scratch_directory();
+        let path = write_gltf(&directory, accessors, serde_json::json!({ "POSITION": 0, attribute: 1 }));
+        let result = model_loader::import_model(&path);
+        fs::remove_dir_all(&directory).unwrap();
+
+        match result {
+            Ok(_) => panic!("loaded a glTF whose {} doesn't match its positions", attribute),
+            Err(error) => assert!(error.to_string().contains(attribute), "{}", error)
+        }
+    }
+}
+
+#[test]
+fn gltf_accessor_counts_are_checked_before_allocating() {
+    let huge = usize::MAX / 4;
+    for accessor in [
+        vec3_accessor(0, huge),
+        serde_json::json!({ "componentType": 5126, "count": huge, "type": "VEC3" }),
+        serde_json::json!({ "componentType": 5126, "count": 1u64 << 40, "type": "VEC3" })
+    ] {
+        let directory = scratch_directory();
+        let path = write_gltf(&directory, serde_json::json!([accessor.clone()]), serde_json::json!({ "POSITION": 0 }));
+        let result = model_loader::import_model(&path);
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(result.is_err(), "loaded {}", accessor);
+    }
+}
+
+
+#[test]
+fn gltf_nodes_must_form_a_tree() {
+    let shared_children = (0..30).map(|index| if index < 29 {
+        serde_json::json!({ "mesh": 0, "children": [index + 1, index + 1] })
+    } else {
+        serde_json::json!({ "mesh": 0 })
+    }).collect::<Vec<_>>();
+    let two_parents = vec![
+        serde_json::json!({ "children": [2] }),
+        serde_json::json!({ "children": [2] }),
+        serde_json::json!({ "mesh": 0 })
+    ];
+    let cycle = vec![serde_json::json!({ "mesh": 0, "children": [1] }), serde_json::json!({ "children": [0] })];
+
+    for (nodes, roots) in [(shared_children, vec![0]), (two_parents, vec![0, 1]), (cycle, vec![0])] {
+        let directory = scratch_directory();
+        let path = write_gltf(&directory, serde_json::json!([vec3_accessor(0, 3)]), serde_json::json!({ "POSITION": 0 }));
+
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["nodes"] = serde_json::Value::from(nodes);
+        json["scenes"] = serde_json::json!([{ "nodes": roots }]);
+        fs::write(&path, json.to_string()).unwrap();
+
+        let result = model_loader::import_model(&path);
+        fs::remove_dir_all(&directory).unwrap();
+
+        match result {
+            Ok(_) => panic!("loaded nodes that don't form a tree"),
+            Err(error) => assert!(error.to_string().contains("tree"), "{}", error)
+        }
+    }
+}
+
+/// Saves `contents` as `name` in a scratch directory and imports it from there.
+fn import_file(name: &str, contents: &[u8]) -> Result<model_loader::Model, model_loader::ModelError> {
+    let directory = scratch_directory();
+    let path = directory.join(name);
+    fs::write(&path, contents).unwrap();
+    let result = model_loader::import_model(&path);
+    fs::remove_dir_all(&directory).unwrap();
+    result
+}
+
+const PLY_HEADER: &str = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\n";
+
+#[test]
+fn ply_quads_are_split_into_triangles() {
+    let ply = format!(
+        "{}element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n",
+        PLY_HEADER
+    );
+    let model = import_file("quad.ply", ply.as_bytes()).unwrap();
+
+    assert_eq!(model.vertices.len() / model.layout.stride, 4);
+    assert_eq!(model.indices.len(), 6);
+}
+
+#[test]
+fn ply_face_indices_must_be_whole_numbers() {
+    for (item, face) in [("int", "3 0 -1 2"), ("float", "3 0 1.5 2")] {
+        let ply = format!(
+            "{}element face 1\nproperty list uchar {} vertex_indices\nend_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n{}\n",
+            PLY_HEADER, item, face
+        );
+        match import_file("broken.ply", ply.as_bytes()) {
+            Ok(_) => panic!("loaded a face with the indices {}", face),
+            Err(error) => assert!(error.to_string().contains("face 0"), "{}", error)
+        }
+    }
+}
+
+#[test]
+fn ply_face_counts_are_not_trusted() {
+    let ply = format!(
+        "{}element face {}\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n3 0 1 2\n",
+        PLY_HEADER, usize::MAX
+    );
+    assert!(import_file("huge.ply", ply.as_bytes()).is_err());
+}
+
+#[test]
+fn ply_vertices_need_positions() {
+    let ply = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float a\nproperty float b\nproperty float c\n\
+        element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+    match import_file("unplaced.ply", ply.as_bytes()) {
+        Ok(_) => panic!("loaded vertices without positions"),
+        Err(error) => assert!(error.to_string().contains("x, y or z"), "{}", error)
+    }
+}
+
+#[test]
+fn stl_ascii_and_binary_load_the_same_triangles() {
+    let ascii = "solid square\n\
+        facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 1 1 0\nendloop\nendfacet\n\
+        facet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 1 0\nvertex 0 1 0\nendloop\nendfacet\n\
+        endsolid square\n";
+
+    let mut binary = vec![0u8; 80];
+    binary.extend_from_slice(&2u32.to_le_bytes());
+    for triangle in [[[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]], [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]] {
+        for vector in [[0.0f32, 0.0, 1.0]].iter().chain(triangle.iter()) {
+            for value in vector {
+                binary.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        binary.extend_from_slice(&[0, 0]);
+    }
+
+    let from_ascii = import_file("square.stl", ascii.as_bytes()).unwrap();
+    let from_binary = import_file("square.stl", &binary).unwrap();
+
+    // The two triangles face the same way, so they share their corners.
+    assert_eq!(from_ascii.vertices.len() / from_ascii.layout.stride, 4);
+    assert_eq!(from_ascii.indices.len(), 6);
+    assert_eq!(from_ascii.vertices, from_binary.vertices);
+    assert_eq!(from_ascii.indices, from_binary.indices);
+
+    // Binary files often start with "solid" too, and some have padding on the end.
+    binary[..12].copy_from_slice(b"solid square");
+    binary.push(0);
+    let padded = import_file("square.stl", &binary).unwrap();
+    assert_eq!(padded.vertices, from_ascii.vertices);
+    assert_eq!(padded.indices, from_ascii.indices);
+}
+
+#[test]
+fn stl_triangle_counts_are_checked() {
+    let mut binary = vec![0u8; 80];
+    binary.extend_from_slice(&1000u32.to_le_bytes());
+    binary.extend_from_slice(&[0; 50]);
+    assert!(import_file("short.stl", &binary).is_err());
+
+    // Files with nothing in them are errors rather than empty models.
+    assert!(import_file("empty.stl", b"solid empty\nendsolid empty\n").is_err());
+    assert!(import_file("empty.stl", &[0; 84]).is_err());
+}
+
+
+const TWO_MATERIALS: &str = "mtllib scene.mtl\no square\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+    g left\nusemtl red\nf 1 2 3\ng right\nusemtl blue\nf 1 3 4\n";
+
+/// Writes an OBJ with two materials next to its MTL file, and returns the path of the OBJ.
+fn write_two_materials(directory: &Path) -> PathBuf {
+    fs::write(directory.join("scene.mtl"), "newmtl red\nKd 1 0 0\nnewmtl blue\nKd 0 0 1\n").unwrap();
+    let path = directory.join("scene.obj");
+    fs::write(&path, TWO_MATERIALS).unwrap();
+    path
+}
+
+#[test]
+fn caches_load_back_the_same_model() {
+    let directory = scratch_directory();
+    let path = write_two_materials(&directory);
+
+    let model = model_loader::import_model(&path).unwrap();
+    let cache = model_loader::cache_path(&path);
+    model_loader::save_cache(&model, &cache).unwrap();
+    let cached = model_loader::load_cache(&cache).unwrap().unwrap();
+    let loaded = model_loader::load_model(&path).unwrap();
+    fs::remove_dir_all(&directory).unwrap();
+
+    for other in [&cached, &loaded] {
+        assert_eq!(other.vertices, model.vertices);
+        assert_eq!(other.indices, model.indices);
+        assert_eq!(other.materials.len(), 2);
+        assert_eq!(other.materials[1].diffuse_color, vec![0.0, 0.0, 1.0]);
+
+        let ranges = |model: &model_loader::Model| -> Vec<(usize, usize, usize)> {
+            model.submeshes.iter().map(|submesh| (submesh.material_index, submesh.start, submesh.count)).collect()
+        };
+        assert_eq!(ranges(other), ranges(&model));
+        assert_eq!(other.objects[0].groups.len(), 2);
+        assert_eq!(other.objects[0].groups[1].submeshes, model.objects[0].groups[1].submeshes);
+    }
+}
+
+#[test]
+fn corrupt_caches_are_rejected() {
+    let directory = scratch_directory();
+    let path = write_two_materials(&directory);
+    let cache = model_loader::cache_path(&path);
+
+    let breakages: [fn(&mut model_loader::Model); 5] = [
+        |model| model.submeshes[1].material_index = 2,
+        |model| model.submeshes[1].count = 6,
+        |model| model.objects[0].count = 7,
+        |model| model.objects[0].submeshes = 0..3,
+        |model| model.objects[0].groups[1].submeshes.start = 3
+    ];
+
+    let mut results = vec![];
+    for breakage in breakages {
+        let mut model = model_loader::import_model(&path).unwrap();
+        breakage(&mut model);
+        model_loader::save_cache(&model, &cache).unwrap();
+        results.push(model_loader::load_cache(&cache).map(|model| model.is_some()));
+    }
+
+    // A flipped bit anywhere in the body is caught by the checksum.
+    let model = model_loader::import_model(&path).unwrap();
+    model_loader::save_cache(&model, &cache).unwrap();
+    let mut bytes = fs::read(&cache).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    fs::write(&cache, &bytes).unwrap();
+    results.push(model_loader::load_cache(&cache).map(|model| model.is_some()));
+
+    fs::remove_dir_all(&directory).unwrap();
+
+    for (case, result) in results.iter().enumerate() {
+        assert!(result.is_err(), "case {} loaded: {:?}", case, result);
+    }
+}
+
+
+/// Reads an OBJ from memory, with no material files.
+fn read_obj(obj: &str) -> model_loader::Model {
+    model_loader::read_obj(obj.as_bytes(), Path::new("memory/test.obj")).unwrap()
+}
+
+/// The triangles drawn by each submesh, by material name.
+fn drawn_by_material(model: &model_loader::Model) -> Vec<(String, usize)> {
+    model.submeshes.iter()
+        .map(|submesh| (model.materials[submesh.material_index].name.clone(), submesh.count / 3))
+        .collect()
+}
+
+#[test]
+fn faces_without_a_material_are_still_drawn() {
+    let model = read_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\no other\nf 3 2 1\n");
+    assert_eq!(drawn_by_material(&model), vec![(String::from("default"), 1), (String::from("default"), 1)]);
+    assert_eq!(model.objects[1].submeshes, 1..2);
+
+    let mut resolver = model_loader::MemoryResolver::new();
+    resolver.add("memory/scene.mtl", "newmtl red\nKd 1 0 0\n");
+    let obj = "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 3 2 1\n";
+    let model = model_loader::read_obj_with(obj.as_bytes(), Path::new("memory/scene.obj"), &resolver).unwrap();
+    assert_eq!(drawn_by_material(&model), vec![(String::from("default"), 1), (String::from("red"), 1)]);
+
+    // Every level of detail still covers the whole model.
+    for lod in model.generate_lods(3, 0.5, f32::INFINITY) {
+        let drawn: usize = lod.submeshes.iter().map(|submesh| submesh.count).sum();
+        assert_eq!(drawn, lod.indices.len());
+    }
+}
+
+#[test]
+fn relative_indices_work_across_chunks() {
+    // Well over a megabyte, so it's split into several chunks that are parsed separately.
+    let mut relative = String::new();
+    let mut absolute = String::new();
+    let mut triangle = 0;
+    while relative.len() < 3 << 20 {
+        let line = format!("v {} 0 0\nv {} 1 0\nv {} 0 1\n", triangle, triangle, triangle + 1);
+        relative.push_str(&line);
+        absolute.push_str(&line);
+        relative.push_str("f -3 -2 -1\n");
+        writeln!(absolute, "f {} {} {}", triangle * 3 + 1, triangle * 3 + 2, triangle * 3 + 3).unwrap();
+        triangle += 1;
+    }
+
+    let from_relative = read_obj(&relative);
+    let from_absolute = read_obj(&absolute);
+
+    assert_eq!(from_relative.indices.len(), triangle * 3);
+    assert_eq!(from_relative.vertices, from_absolute.vertices);
+    assert_eq!(from_relative.indices, from_absolute.indices);
+}
+
+
+/// One attribute of one vertex.
+fn attribute(model: &model_loader::Model, kind: model_loader::AttributeKind, vertex: u32) -> &[f32] {
+    let attribute = model.layout.get(kind).unwrap();
+    let start = vertex as usize * model.layout.stride + attribute.offset;
+    &model.vertices[start..start + attribute.size]
+}
+
+/// The normal of each triangle, scaled by twice its area.
+fn triangle_normals(model: &model_loader::Model) -> Vec<[f32; 3]> {
+    let position = |vertex: u32| attribute(model, model_loader::AttributeKind::Position, vertex);
+    model.indices.chunks(3).map(|triangle| {
+        let (a, b, c) = (position(triangle[0]), position(triangle[1]), position(triangle[2]));
+        let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+        [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]]
+    }).collect()
+}
+
+#[test]
+fn concave_faces_are_triangulated() {
+    // An L shape, whose fan from the first corner would go outside it.
+    let model = read_obj("v 0 0 0\nv 2 0 0\nv 2 1 0\nv 1 1 0\nv 1 2 0\nv 0 2 0\nf 1 2 3 4 5 6\n");
+    let normals = triangle_normals(&model);
+
+    assert_eq!(normals.len(), 4);
+    assert!(normals.iter().all(|normal| normal[2] > 0.0), "{:?}", normals);
+    let area: f32 = normals.iter().map(|normal| normal[2] / 2.0).sum();
+    assert!((area - 3.0).abs() < 1e-5, "{}", area);
+}
+
+#[test]
+fn collinear_corners_dont_make_slivers() {
+    // A square with an extra corner halfway along the bottom edge.
+    let model = read_obj("v 0 0 0\nv 1 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nf 1 2 3 4 5\n");
+    let normals = triangle_normals(&model);
+
+    assert_eq!(normals.len(), 3);
+    assert!(normals.iter().all(|normal| normal[2] > 0.0), "{:?}", normals);
+    let area: f32 = normals.iter().map(|normal| normal[2] / 2.0).sum();
+    assert!((area - 4.0).abs() < 1e-5, "{}", area);
+}
+
+#[test]
+fn negative_indices_count_back_from_the_face() {
+    let elements = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvn 0 0 1\n";
+    let relative = read_obj(&format!("{}f -3/-3/-1 -2/-2/-1 -1/-1/-1\nv 5 5 5\n", elements));
+    let absolute = read_obj(&format!("{}f 1/1/1 2/2/1 3/3/1\nv 5 5 5\n", elements));
+
+    assert_eq!(relative.vertices, absolute.vertices);
+    assert_eq!(relative.indices, absolute.indices);
+
+    let too_far = format!("{}f -4 -2 -1\n", elements);
+    assert!(model_loader::read_obj(too_far.as_bytes(), Path::new("memory/test.obj")).is_err());
+}
+
+#[test]
+fn missing_normals_are_generated() {
+    use model_loader::AttributeKind::Normal;
+
+    // Two faces folded along the x axis, one flat on the ground and one standing up.
+    let corners = "v 0 0 0\nv 1 0 0\nv 1 0 -1\nv 0 1 0\n";
+    let flat = read_obj(&format!("{}f 1 2 3\nf 1 2 4\n", corners));
+    let smooth = read_obj(&format!("{}s 1\nf 1 2 3\nf 1 2 4\n", corners));
+
+    for model in [&flat, &smooth] {
+        assert_eq!(model.layout.stride, 8);
+        assert_eq!(model.missing_normals, 6);
+    }
+
+    // Without smoothing, the shared edge is split so each face keeps its own normal.
+    assert_eq!(flat.vertices.len() / flat.layout.stride, 6);
+    assert_eq!(attribute(&flat, Normal, flat.indices[0]), &[0.0, 1.0, 0.0]);
+    assert_eq!(attribute(&flat, Normal, flat.indices[3]), &[0.0, 0.0, 1.0]);
+
+    // With it, the shared corners average the two.
+    assert_eq!(smooth.vertices.len() / smooth.layout.stride, 4);
+    let shared = attribute(&smooth, Normal, smooth.indices[0]);
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    assert!(shared[0].abs() < 1e-5 && (shared[1] - half).abs() < 1e-5 && (shared[2] - half).abs() < 1e-5, "{:?}", shared);
+
+    // A bare "s" turns smoothing off, like "s off".
+    let bare = read_obj(&format!("{}s 1\ns\nf 1 2 3\nf 1 2 4\n", corners));
+    assert_eq!(bare.vertices, flat.vertices);
+}
+
+#[test]
+fn shared_corners_become_one_vertex() {
+    // A quad as two triangles. The last corner has different texture coordinates, so it can't be
+    // shared with the first one even though they're at the same position.
+    let model = read_obj(
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 1\nvn 0 0 1\n\
+        f 1/1/1 2/1/1 3/1/1\nf 1/1/1 3/1/1 4/1/1\nf 1/2/1 2/1/1 4/1/1\n"
+    );
+
+    assert_eq!(model.vertices.len() / model.layout.stride, 5);
+    assert_eq!(model.indices.len(), 9);
+    assert_eq!(model.indices[0], model.indices[3]);
+    assert_eq!(model.indices[2], model.indices[4]);
+    assert_ne!(model.indices[0], model.indices[6]);
+
+    // Without normals in the file, a flat quad's two triangles still share their corners, but
+    // the next quad over gets its own since it faces another way.
+    let quads = read_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nv 0 1 1\nv 1 1 1\nf 1 2 4 3\nf 3 4 6 5\n");
+    assert_eq!(quads.vertices.len() / quads.layout.stride, 8);
+    assert_eq!(quads.indices.len(), 12);
+}
+
+#[test]
+fn objects_and_groups_have_their_own_ranges() {
+    let mut resolver = model_loader::MemoryResolver::new();
+    resolver.add("memory/scene.mtl", "newmtl hull\nKd 1 1 1\nnewmtl flame\nKd 1 0 0\n");
+    let obj = "mtllib scene.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+        o ship\nusemtl hull\nf 1 2 3\ng left wing\nf 1 2 3\nf 1 2 3\ng exhaust\nusemtl flame\nf 1 2 3\n\
+        o light\nf 1 2 3\n";
+    let model = model_loader::read_obj_with(obj.as_bytes(), Path::new("memory/scene.obj"), &resolver).unwrap();
+
+    let ship = model.object("ship").unwrap();
+    assert_eq!((ship.start, ship.count), (0, 12));
+    assert_eq!(ship.groups.len(), 2);
+
+    // Either of a group's names finds it.
+    let wing = ship.group("wing").unwrap();
+    assert_eq!(wing.names, vec![String::from("left"), String::from("wing")]);
+    assert_eq!((wing.start, wing.count), (3, 6));
+    assert_eq!(drawn_by_material(&model)[wing.submeshes.clone()], [(String::from("hull"), 2)]);
+
+    let exhaust = ship.group("exhaust").unwrap();
+    assert_eq!((exhaust.start, exhaust.count), (9, 3));
+    assert_eq!(drawn_by_material(&model)[exhaust.submeshes.clone()], [(String::from("flame"), 1)]);
+
+    // The material carries on into the next object, in a submesh of its own.
+    let light = model.object("light").unwrap();
+    assert_eq!((light.start, light.count), (12, 3));
+    assert_eq!(drawn_by_material(&model)[light.submeshes.clone()], [(String::from("flame"), 1)]);
+    assert!(model.object("nothing").is_none());
+}
+
+#[test]
+fn tangents_follow_the_texture_coordinates() {
+    use model_loader::AttributeKind::{Normal, Tangent};
+
+    // Two quads side by side. The right one has its texture mirrored, like the two halves of a
+    // symmetrical model often do.
+    let mut model = read_obj(
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 2 0 0\nv 2 1 0\n\
+        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\n\
+        f 1/1/1 2/2/1 3/3/1 4/4/1\nf 2/2/1 5/1/1 6/4/1 3/3/1\n"
+    );
+    let stride = model.layout.stride;
+    assert!(model.generate_tangents());
+    assert_eq!(model.layout.stride, stride + 4);
+
+    for (triangle, expected) in [(0, [1.0, 0.0, 0.0, 1.0]), (2, [-1.0, 0.0, 0.0, -1.0])] {
+        for &vertex in &model.indices[triangle * 3..triangle * 3 + 3] {
+            let tangent = attribute(&model, Tangent, vertex);
+            assert!(tangent.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-5), "{:?}", tangent);
+            assert_eq!(attribute(&model, Normal, vertex), &[0.0, 0.0, 1.0]);
+        }
+    }
+}